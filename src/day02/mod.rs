@@ -12,6 +12,37 @@ impl CPU<i32> {
         *self.mmu.get_mut(2) = verb;
         self.run_with_no_input()
     }
+
+    // Runs the program once with symbolic noun and verb, then solves the resulting linear
+    // expression for the verb one noun at a time
+    #[allow(dead_code)]
+    fn solve_noun_and_verb(&self, target: i32) -> Option<(i32, i32)> {
+        let mut executor = self.symbolic();
+        executor.set_symbol(1, "noun");
+        executor.set_symbol(2, "verb");
+        let paths = executor.run().ok()?;
+
+        for path in &paths {
+            let result = path.cell(0);
+            if result.degree() > 1 {
+                continue;
+            }
+            let slope = result.coefficient(&["verb"]);
+            for noun in 0..100 {
+                let rest = target - result.evaluate(&[("noun", noun), ("verb", 0)])?;
+                if slope == 0 || rest % slope != 0 {
+                    continue;
+                }
+                let verb = rest / slope;
+                if (0..100).contains(&verb)
+                    && path.is_satisfied_by(&[("noun", noun), ("verb", verb)])
+                {
+                    return Some((noun, verb));
+                }
+            }
+        }
+        None
+    }
 }
 
 #[cfg(test)]
@@ -46,6 +77,7 @@ mod tests {
             panic!("No solution found")
         };
 
-        assert_eq!(find_solution(), 5064)
+        assert_eq!(find_solution(), 5064);
+        assert_eq!(cpu.solve_noun_and_verb(19_690_720), Some((50, 64)));
     }
 }
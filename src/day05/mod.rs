@@ -1,16 +1,13 @@
-use num::{PrimInt, Signed};
-
-use std::fmt;
 use std::str::FromStr;
 
-use crate::utils::intcode::CPU;
+use crate::utils::intcode::{Word, CPU};
 
 #[allow(dead_code)]
 fn diagnostic_code<T>(input: &str, system_id: T) -> T
 where
-    T: PrimInt + Signed + FromStr + fmt::Display,
+    T: Word + FromStr,
 {
-    CPU::from_source(input).outputs_with(|| system_id).last().unwrap()
+    CPU::from_source(input).outputs_with(move || system_id.clone()).last().unwrap()
 }
 
 #[cfg(test)]
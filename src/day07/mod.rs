@@ -1,79 +1,33 @@
 use itertools::Itertools;
 
-use crate::utils::intcode::{ExecutionResult, CPU};
+use crate::utils::intcode::{topology::Topology, CPU};
 
-#[derive(Debug, Clone)]
-struct Amplifier {
-    cpu: CPU<i32>,
-}
-
-impl Amplifier {
-    fn new(mut cpu: CPU<i32>, phase: i32) -> Amplifier {
-        // Run the CPU until the first input before returning
-        loop {
-            let instruction = cpu.fetch_instruction().expect("No instruction found");
-            if let ExecutionResult::YieldedInput(sink) = cpu.execute_instruction(instruction) {
-                sink(phase);
-                break;
-            }
-        }
-
-        Amplifier { cpu }
-    }
-
-    fn run(&mut self, input: i32) -> Option<i32> {
-        loop {
-            let instruction = self.cpu.fetch_instruction()?;
-            match self.cpu.execute_instruction(instruction) {
-                ExecutionResult::YieldedInput(sink) => sink(input),
-                ExecutionResult::YieldedOutput(value) => break Some(value),
-                ExecutionResult::Completed => break None,
-                ExecutionResult::Running => {}
-            }
-        }
-    }
-}
-
-#[allow(dead_code)]
-fn find_largest_output_signal(input: &str) -> i32 {
+fn largest_signal(input: &str, phases: impl Iterator<Item = i32> + Clone, feedback: bool) -> i32 {
     let cpu = CPU::from_source(input);
 
-    (0..5)
+    phases
         .permutations(5)
         .map(|phases| {
-            phases
-                .into_iter()
-                .map(|phase| Amplifier::new(cpu.clone(), phase))
-                .fold(0, |input, mut amplifier| amplifier.run(input).unwrap())
+            let mut amplifiers = if feedback {
+                Topology::ring(&cpu, &phases)
+            } else {
+                Topology::pipeline(&cpu, &phases)
+            };
+            amplifiers.send(0, 0);
+            *amplifiers.run().unwrap().outputs[4].last().unwrap()
         })
         .max()
         .unwrap()
 }
 
 #[allow(dead_code)]
-fn find_largest_output_signal_with_feedback(input: &str) -> i32 {
-    let cpu = CPU::from_source(input);
-
-    (5..10)
-        .permutations(5)
-        .map(|phases| {
-            let mut amplifiers: Vec<_> =
-                phases.into_iter().map(|phase| Amplifier::new(cpu.clone(), phase)).collect();
-
-            let mut current_value = 0;
+fn find_largest_output_signal(input: &str) -> i32 {
+    largest_signal(input, 0..5, false)
+}
 
-            loop {
-                for amplifier in amplifiers.iter_mut() {
-                    if let Some(next_value) = amplifier.run(current_value) {
-                        current_value = next_value;
-                    } else {
-                        return current_value;
-                    }
-                }
-            }
-        })
-        .max()
-        .unwrap()
+#[allow(dead_code)]
+fn find_largest_output_signal_with_feedback(input: &str) -> i32 {
+    largest_signal(input, 5..10, true)
 }
 
 #[cfg(test)]
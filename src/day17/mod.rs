@@ -1,7 +1,5 @@
-use std::char;
 use std::collections::HashMap;
 use std::fmt::Write;
-use std::iter::once;
 
 use fancy_regex::Regex as FancyRegex;
 use itertools::Itertools;
@@ -9,7 +7,7 @@ use lazy_static::lazy_static;
 
 use crate::utils::{
     direction::{Direction, Rotation},
-    intcode::CPU,
+    intcode::{ascii::AsciiCpu, CPU},
     Vec2,
 };
 
@@ -19,8 +17,8 @@ enum Tile {
 }
 
 fn create_image(input: &str) -> String {
-    let mut cpu = CPU::<i64>::from_source(input);
-    cpu.outputs().filter_map(|i| char::from_u32(i as u32)).collect()
+    let lines = AsciiCpu::from_source(input).read_lines().unwrap();
+    lines.join("\n")
 }
 
 fn parse_image(image: &str) -> HashMap<Vec2<i32>, Tile> {
@@ -161,35 +159,30 @@ fn part2(input: &str) -> i64 {
 
     static SEGMENT_SYMBOLS: [char; 3] = ['A', 'B', 'C'];
 
-    let mut message = String::new();
+    let mut cpu = CPU::<i64>::from_source(input);
+    *cpu.mmu.get_mut(0) = 2;
+    let mut cpu = AsciiCpu::new(cpu);
 
-    message.extend(
-        instructions.iter().map(|&i| SEGMENT_SYMBOLS[i]).intersperse(',').chain(once('\n')),
-    );
+    cpu.send_line(&instructions.iter().map(|&i| SEGMENT_SYMBOLS[i]).join(","));
 
     for &segment in &segments {
+        let mut line = String::new();
         for (i, (rot, steps)) in segment.iter().enumerate() {
             if i != 0 {
-                message.push(',');
+                line.push(',');
             }
-            message.push(match rot {
+            line.push(match rot {
                 Rotation::CCW => 'R',
                 Rotation::CW => 'L',
             });
-            message.push(',');
-            write!(&mut message, "{}", steps).unwrap();
+            write!(&mut line, ",{}", steps).unwrap();
         }
-        message.push('\n');
+        cpu.send_line(&line);
     }
 
-    message.push_str("n\n");
-
-    let mut cpu = CPU::<i64>::from_source(input);
-    *cpu.mmu.get_mut(0) = 2;
-
-    let mut message_iter = message.bytes().map(|b| b as i64);
+    cpu.send_line("n");
 
-    cpu.outputs_with(|| message_iter.next().unwrap()).last().unwrap()
+    cpu.answer().unwrap().unwrap()
 }
 
 #[cfg(test)]
@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use super::memory::{DenseMemory, Memory};
+use super::resume::Status;
+use super::{CpuError, CPU};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsciiEvent {
+    Line(String),
+    // Values outside the ASCII range are usually the program's final answer
+    Value(i64),
+    NeedsInput,
+    Halted,
+}
+
+#[derive(Debug, Clone)]
+pub struct AsciiCpu<M = DenseMemory<i64>> {
+    cpu: CPU<i64, M>,
+    line: String,
+}
+
+impl AsciiCpu {
+    pub fn from_source(input: &str) -> AsciiCpu {
+        AsciiCpu::new(CPU::from_source(input))
+    }
+}
+
+impl<M: Memory<i64>> AsciiCpu<M> {
+    pub fn new(cpu: CPU<i64, M>) -> AsciiCpu<M> {
+        AsciiCpu { cpu, line: String::new() }
+    }
+
+    pub fn cpu(&self) -> &CPU<i64, M> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU<i64, M> {
+        &mut self.cpu
+    }
+
+    pub fn send_line(&mut self, line: &str) {
+        self.cpu.provide_inputs(line.bytes().chain(Some(b'\n')).map(i64::from));
+    }
+
+    // A partial line is flushed before reporting that the program waits for input, so prompts
+    // without a trailing newline are still visible
+    pub fn next_event(&mut self) -> Result<AsciiEvent, CpuError<i64>> {
+        loop {
+            match self.cpu.try_run()? {
+                Status::NeedsInput if self.line.is_empty() => return Ok(AsciiEvent::NeedsInput),
+                Status::Output(10) => return Ok(AsciiEvent::Line(std::mem::take(&mut self.line))),
+                Status::Output(value @ 0..=127) => self.line.push(value as u8 as char),
+                Status::Output(value) => return Ok(AsciiEvent::Value(value)),
+                Status::Halted if self.line.is_empty() => return Ok(AsciiEvent::Halted),
+                Status::NeedsInput | Status::Halted => {
+                    return Ok(AsciiEvent::Line(std::mem::take(&mut self.line)))
+                }
+            }
+        }
+    }
+
+    // Collects the lines printed until the program waits for input or halts
+    pub fn read_lines(&mut self) -> Result<Vec<String>, CpuError<i64>> {
+        let mut lines = Vec::new();
+        loop {
+            match self.next_event()? {
+                AsciiEvent::Line(line) => lines.push(line),
+                AsciiEvent::Value(_) => {}
+                AsciiEvent::NeedsInput | AsciiEvent::Halted => return Ok(lines),
+            }
+        }
+    }
+
+    // Runs the program to completion with the queued input and returns its last non-ASCII value
+    pub fn answer(&mut self) -> Result<Option<i64>, CpuError<i64>> {
+        let mut answer = None;
+        loop {
+            match self.next_event()? {
+                AsciiEvent::Line(_) => {}
+                AsciiEvent::Value(value) => answer = Some(value),
+                AsciiEvent::NeedsInput | AsciiEvent::Halted => return Ok(answer),
+            }
+        }
+    }
+
+    pub fn interact<R: BufRead, W: Write>(
+        &mut self,
+        mut input: R,
+        mut output: W,
+    ) -> Result<Option<i64>, Box<dyn Error>> {
+        let mut answer = None;
+        loop {
+            match self.next_event()? {
+                AsciiEvent::Line(line) => writeln!(output, "{}", line)?,
+                AsciiEvent::Value(value) => {
+                    writeln!(output, "{}", value)?;
+                    answer = Some(value);
+                }
+                AsciiEvent::NeedsInput => {
+                    output.flush()?;
+                    let mut line = String::new();
+                    if input.read_line(&mut line)? == 0 {
+                        return Ok(answer);
+                    }
+                    self.send_line(line.trim_end_matches(&['\r', '\n'][..]));
+                }
+                AsciiEvent::Halted => return Ok(answer),
+            }
+        }
+    }
+
+    pub fn play(&mut self) -> Result<Option<i64>, Box<dyn Error>> {
+        let stdin = io::stdin();
+        self.interact(stdin.lock(), io::stdout())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::asm::assemble;
+
+    // Prompts for a name, greets it and prints its length after a non-ASCII marker
+    const GREETER: &str = "
+                WRT IM(63)
+                WRT IM(10)
+        read:   RED IN(char)
+                EQL IN(char), IM(10), IN(done)
+                JIT IN(done), IM(greet)
+                ADD IN(length), IM(1), IN(length)
+                JIT IM(1), IM(read)
+        greet:  WRT IM(104)
+                WRT IM(105)
+                WRT IM(10)
+                WRT IM(1000)
+                ADD IN(length), IM(48), IN(length)
+                WRT IN(length)
+                WRT IM(33)
+                BRK
+        char:   DAT 0
+        done:   DAT 0
+        length: DAT 0
+    ";
+
+    fn greeter() -> AsciiCpu {
+        AsciiCpu::new(CPU::new(assemble(GREETER).unwrap()))
+    }
+
+    #[test]
+    fn lines_and_values() {
+        let mut cpu = greeter();
+        assert_eq!(cpu.next_event(), Ok(AsciiEvent::Line("?".to_string())));
+        assert_eq!(cpu.next_event(), Ok(AsciiEvent::NeedsInput));
+
+        cpu.send_line("intcode");
+        assert_eq!(cpu.next_event(), Ok(AsciiEvent::Line("hi".to_string())));
+        assert_eq!(cpu.next_event(), Ok(AsciiEvent::Value(1000)));
+        assert_eq!(cpu.next_event(), Ok(AsciiEvent::Line("7!".to_string())));
+        assert_eq!(cpu.next_event(), Ok(AsciiEvent::Halted));
+    }
+
+    #[test]
+    fn interactive_session() {
+        let mut output = Vec::new();
+        let answer = greeter().interact("ab\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(answer, Some(1000));
+        assert_eq!(String::from_utf8(output).unwrap(), "?\nhi\n1000\n2!\n");
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use super::extension::Extensions;
+use super::Word;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    UnknownMnemonic { line: usize, mnemonic: String },
+    InvalidOperand { line: usize, operand: String },
+    WrongOperandCount { line: usize, expected: usize, found: usize },
+    UndefinedLabel { line: usize, label: String },
+    DuplicateLabel { line: usize, label: String },
+    AddressOutOfOrder { line: usize, address: usize },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "Unknown mnemonic {} on line {}", mnemonic, line)
+            }
+            Self::InvalidOperand { line, operand } => {
+                write!(f, "Invalid operand {} on line {}", operand, line)
+            }
+            Self::WrongOperandCount { line, expected, found } => {
+                write!(f, "Expected {} operands but found {} on line {}", expected, found, line)
+            }
+            Self::UndefinedLabel { line, label } => {
+                write!(f, "Undefined label {} on line {}", label, line)
+            }
+            Self::DuplicateLabel { line, label } => {
+                write!(f, "Duplicate label {} on line {}", label, line)
+            }
+            Self::AddressOutOfOrder { line, address } => {
+                write!(f, "Address {} is behind the current position on line {}", address, line)
+            }
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Debug, Clone)]
+enum Value<T> {
+    Literal(T),
+    Label(String, T),
+}
+
+#[derive(Debug, Clone)]
+struct Cell<T> {
+    line: usize,
+    value: Value<T>,
+    // Parameter modes are folded into the opcode word once all operands are known
+    modes: Vec<usize>,
+}
+
+const MNEMONICS: [(&str, usize, usize); 10] = [
+    ("ADD", 1, 3),
+    ("MUL", 2, 3),
+    ("RED", 3, 1),
+    ("WRT", 4, 1),
+    ("JIT", 5, 2),
+    ("JIF", 6, 2),
+    ("LST", 7, 3),
+    ("EQL", 8, 3),
+    ("RBO", 9, 1),
+    ("BRK", 99, 0),
+];
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value<T: Word>(line: usize, text: &str) -> Result<Value<T>, AsmError> {
+    let text = text.trim();
+    let invalid = || AsmError::InvalidOperand { line, operand: text.to_string() };
+
+    if let Ok(value) = T::from_str_radix(text, 10) {
+        return Ok(Value::Literal(value));
+    }
+
+    let (label, offset) = match text.find(&['+', '-'][..]) {
+        Some(index) => {
+            let offset = text[index + 1..].trim();
+            let offset = T::from_str_radix(offset, 10).map_err(|_| invalid())?;
+            let offset = if text[index..].starts_with('-') { -offset } else { offset };
+            (text[..index].trim(), offset)
+        }
+        None => (text, T::zero()),
+    };
+
+    if is_label(label) {
+        Ok(Value::Label(label.to_string(), offset))
+    } else {
+        Err(invalid())
+    }
+}
+
+fn parse_operand<T: Word>(line: usize, text: &str) -> Result<(usize, Value<T>), AsmError> {
+    let text = text.trim();
+    let mode = match text.get(..3) {
+        Some("IN(") => 0,
+        Some("IM(") => 1,
+        Some("RE(") => 2,
+        _ => return Err(AsmError::InvalidOperand { line, operand: text.to_string() }),
+    };
+    if !text.ends_with(')') {
+        return Err(AsmError::InvalidOperand { line, operand: text.to_string() });
+    }
+    Ok((mode, parse_value(line, &text[3..text.len() - 1])?))
+}
+
+pub fn assemble<T: Word>(source: &str) -> Result<Vec<T>, AsmError> {
+    assemble_with(source, &Extensions::new())
+}
+
+// Also accepts the mnemonics of registered extensions, like the ones a CPU disassembles to
+pub fn assemble_with<T: Word>(
+    source: &str,
+    extensions: &Extensions<T>,
+) -> Result<Vec<T>, AsmError> {
+    let mut words: Vec<Cell<T>> = Vec::new();
+    let mut labels = HashMap::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut text = text.split(';').next().unwrap().trim();
+
+        while let Some(colon) = text.find(':') {
+            let name = text[..colon].trim();
+            if let Ok(address) = name.parse::<usize>() {
+                if address < words.len() {
+                    return Err(AsmError::AddressOutOfOrder { line, address });
+                }
+                while words.len() < address {
+                    words.push(Cell { line, value: Value::Literal(T::zero()), modes: vec![] });
+                }
+            } else if is_label(name) {
+                if labels.insert(name.to_string(), words.len()).is_some() {
+                    return Err(AsmError::DuplicateLabel { line, label: name.to_string() });
+                }
+            } else {
+                break;
+            }
+            text = text[colon + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(index) => (&text[..index], text[index..].trim()),
+            None => (text, ""),
+        };
+        let operands: Vec<_> = if rest.is_empty() { vec![] } else { rest.split(',').collect() };
+
+        if mnemonic == "DAT" {
+            for operand in operands {
+                words.push(Cell { line, value: parse_value(line, operand)?, modes: vec![] });
+            }
+            continue;
+        }
+
+        let (opcode, arity) = MNEMONICS
+            .iter()
+            .find(|(name, _, _)| *name == mnemonic)
+            .map(|&(_, opcode, arity)| (opcode, arity))
+            .or_else(|| {
+                let (opcode, extension) = extensions.find(mnemonic)?;
+                Some((opcode, extension.arity()))
+            })
+            .ok_or_else(|| AsmError::UnknownMnemonic { line, mnemonic: mnemonic.to_string() })?;
+
+        if operands.len() != arity {
+            return Err(AsmError::WrongOperandCount {
+                line,
+                expected: arity,
+                found: operands.len(),
+            });
+        }
+
+        let operands = operands
+            .into_iter()
+            .map(|operand| parse_operand(line, operand))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let opcode = T::from_usize(opcode).unwrap();
+        let modes = operands.iter().map(|(mode, _)| *mode).collect();
+        words.push(Cell { line, value: Value::Literal(opcode), modes });
+        words.extend(operands.into_iter().map(|(_, value)| Cell { line, value, modes: vec![] }));
+    }
+
+    words
+        .into_iter()
+        .map(|Cell { line, value, modes }| {
+            let value = match value {
+                Value::Literal(value) => value,
+                Value::Label(label, offset) => match labels.get(&label) {
+                    Some(&address) => T::from_usize(address).unwrap() + offset,
+                    None => return Err(AsmError::UndefinedLabel { line, label }),
+                },
+            };
+            let modes = modes.iter().rev().fold(0, |acc, mode| acc * 10 + mode);
+            Ok(value + T::from_usize(modes * 100).unwrap())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::CPU;
+
+    #[test]
+    fn assembles_programs() {
+        let source = "
+            ; Counts down from the input and outputs every value
+                    RED IN(counter)
+            loop:   WRT IN(counter)
+                    ADD IN(counter), IM(-1), IN(counter)
+                    JIT IN(counter), IM(loop)
+                    BRK
+            counter: DAT 0
+        ";
+
+        let program = assemble::<i64>(source).unwrap();
+        assert_eq!(program, vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+
+        let outputs: Vec<_> = CPU::new(program).outputs_with(|| 3).collect();
+        assert_eq!(outputs, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn labels_and_relative_operands() {
+        let source = "
+            RBO IM(buffer)
+            ADD IM(21), IM(21), RE(1)
+            WRT IN(buffer+1)
+            BRK
+            buffer: DAT 0, 0
+        ";
+
+        let program = assemble::<i64>(source).unwrap();
+        assert_eq!(program, vec![109, 9, 21101, 21, 21, 1, 4, 10, 99, 0, 0]);
+        assert_eq!(CPU::new(program).outputs().collect::<Vec<_>>(), vec![42]);
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        for &source in &[
+            include_str!("../../day05/input"),
+            include_str!("../../day09/input"),
+            include_str!("../../day13/input"),
+            include_str!("../../day15/input"),
+            include_str!("../../day17/input"),
+        ] {
+            let cpu = CPU::<i64>::from_source(source);
+            let listing = cpu.disassemble().to_string();
+            let program: Vec<_> = source.trim().split(',').map(|s| s.parse().unwrap()).collect();
+            assert_eq!(assemble::<i64>(&listing), Ok(program));
+        }
+
+        // Stray mode digits past the last parameter still decode, but are listed as data
+        let cpu = CPU::<i64>::from_source("1104,5,10099");
+        let listing = cpu.disassemble().to_string();
+        assert_eq!(
+            listing.lines().map(str::trim_end).collect::<Vec<_>>(),
+            vec![
+                "    0: DAT 1104, 5                      ; 1104,5",
+                "    2: DAT 10099                        ; 10099",
+            ]
+        );
+        assert_eq!(assemble::<i64>(&listing), Ok(vec![1104, 5, 10099]));
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        assert_eq!(
+            assemble::<i64>("ADD IM(1), IM(2)"),
+            Err(AsmError::WrongOperandCount { line: 1, expected: 3, found: 2 })
+        );
+        assert_eq!(
+            assemble::<i64>("\nFOO IM(1)"),
+            Err(AsmError::UnknownMnemonic { line: 2, mnemonic: "FOO".to_string() })
+        );
+        assert_eq!(
+            assemble::<i64>("WRT XX(1)"),
+            Err(AsmError::InvalidOperand { line: 1, operand: "XX(1)".to_string() })
+        );
+        assert_eq!(
+            assemble::<i64>("JIT IM(1), IM(end)"),
+            Err(AsmError::UndefinedLabel { line: 1, label: "end".to_string() })
+        );
+        assert_eq!(
+            assemble::<i64>("a: BRK\na: BRK"),
+            Err(AsmError::DuplicateLabel { line: 2, label: "a".to_string() })
+        );
+        assert_eq!(
+            assemble::<i64>("BRK\nBRK\n1: BRK"),
+            Err(AsmError::AddressOutOfOrder { line: 3, address: 1 })
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use super::memory::Memory;
+use super::{CpuError, Word, CPU};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    pub instructions: Option<usize>,
+    pub without_io: Option<usize>,
+    pub detect_loops: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Limits {
+    budget: Budget,
+    executed: usize,
+    since_io: usize,
+    // States seen since the last input or output. Without I/O the CPU is deterministic, so
+    // reaching the same state twice means it will keep repeating forever.
+    seen: HashSet<(usize, isize, u64)>,
+}
+
+pub(super) fn cell_hash<T: Word>(index: usize, value: &T) -> u64 {
+    // Zeroed cells don't contribute, so memory growing with zeros keeps the same fingerprint
+    if value.is_zero() {
+        return 0;
+    }
+    let mut hasher = DefaultHasher::new();
+    (index, value).hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<T, M> CPU<T, M> {
+    pub fn clear_budget(&mut self) {
+        self.limits = None;
+        self.mmu.fingerprint = None;
+    }
+
+    pub fn executed_instructions(&self) -> Option<usize> {
+        self.limits.as_ref().map(|limits| limits.executed)
+    }
+
+    // States seen before memory was replaced wholesale say nothing about the states after it
+    pub(super) fn forget_states(&mut self) {
+        self.mmu.fingerprint = None;
+        if let Some(limits) = &mut self.limits {
+            limits.seen.clear();
+        }
+    }
+
+    // Called for every instruction that reads or outputs a value, extensions included
+    pub(super) fn reset_io(&mut self) {
+        if let Some(limits) = &mut self.limits {
+            limits.since_io = 0;
+            limits.seen.clear();
+        }
+    }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.limits = Some(Box::new(Limits { budget, ..Limits::default() }));
+        self.mmu.fingerprint = None;
+    }
+
+    fn fingerprint(&mut self) -> u64 {
+        match self.mmu.fingerprint {
+            Some(fingerprint) => fingerprint,
+            None => {
+                let fingerprint = self
+                    .mmu
+                    .cells()
+                    .fold(0, |fingerprint, (index, value)| fingerprint ^ cell_hash(index, &value));
+                self.mmu.fingerprint = Some(fingerprint);
+                // Memory may have been patched behind our back, so earlier states prove nothing
+                if let Some(limits) = &mut self.limits {
+                    limits.seen.clear();
+                }
+                fingerprint
+            }
+        }
+    }
+
+    pub(super) fn check_limits(&mut self) -> Result<(), CpuError<T>> {
+        let detect_loops = self.limits.as_ref().is_some_and(|limits| limits.budget.detect_loops);
+        let fingerprint = if detect_loops { Some(self.fingerprint()) } else { None };
+
+        let (pc, rbo) = (self.pc, self.rbo);
+        let limits = self.limits.as_mut().unwrap();
+        limits.executed += 1;
+        limits.since_io += 1;
+
+        let exhausted = limits.budget.instructions.is_some_and(|limit| limits.executed > limit)
+            || limits.budget.without_io.is_some_and(|limit| limits.since_io > limit);
+        if exhausted {
+            limits.executed -= 1;
+            limits.since_io -= 1;
+            return Err(CpuError::BudgetExhausted { pc, word: self.mmu.get(pc) });
+        }
+
+        // An I/O instruction resets these again once it has executed
+        if let Some(fingerprint) = fingerprint {
+            if !limits.seen.insert((pc, rbo, fingerprint)) {
+                return Err(CpuError::InfiniteLoop { pc, word: self.mmu.get(pc) });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::extension::{Effect, Extension};
+    use crate::utils::intcode::memory::SparseMemory;
+    use crate::utils::intcode::parse_program;
+
+    // Outputs its argument
+    struct Echo;
+
+    impl Extension<i64> for Echo {
+        fn mnemonic(&self) -> &'static str {
+            "ECH"
+        }
+
+        fn arity(&self) -> usize {
+            1
+        }
+
+        fn execute(&self, args: &[i64]) -> Option<Effect<i64>> {
+            Some(Effect::Output(args[0]))
+        }
+    }
+
+    fn run(source: &str, budget: Budget) -> Vec<Result<i64, CpuError<i64>>> {
+        let mut cpu = CPU::from_source(source);
+        cpu.set_budget(budget);
+        cpu.try_outputs_with(|| 2).collect()
+    }
+
+    #[test]
+    fn budgets_stop_runaway_programs() {
+        let boost = include_str!("../../day09/input");
+        let budget = Budget { instructions: Some(1000), ..Budget::default() };
+        assert!(matches!(run(boost, budget)[..], [Err(CpuError::BudgetExhausted { .. })]));
+
+        let budget = Budget { instructions: Some(1_000_000), ..Budget::default() };
+        assert_eq!(run(boost, budget), vec![Ok(87571)]);
+
+        // Outputs a counter forever, so only the total budget stops it
+        let counter = "4,9,1001,9,1,9,1105,1,0,0";
+        let budget = Budget { without_io: Some(10), ..Budget::default() };
+        let mut cpu = CPU::<i64>::from_source(counter);
+        cpu.set_budget(budget);
+        assert_eq!(cpu.try_outputs_with(|| 0).take(100).filter(Result::is_ok).count(), 100);
+
+        let budget = Budget { without_io: Some(5), ..Budget::default() };
+        assert_eq!(
+            run("1105,1,0", budget),
+            vec![Err(CpuError::BudgetExhausted { pc: 0, word: 1105 })]
+        );
+
+        let budget = Budget { instructions: Some(10), ..Budget::default() };
+        assert_eq!(
+            run(counter, budget),
+            vec![Ok(0), Ok(1), Ok(2), Ok(3), Err(CpuError::BudgetExhausted { pc: 2, word: 1001 })]
+        );
+    }
+
+    #[test]
+    fn repeated_states_are_infinite_loops() {
+        let budget = Budget { detect_loops: true, ..Budget::default() };
+        assert_eq!(
+            run("104,1,1105,1,2", budget),
+            vec![Ok(1), Err(CpuError::InfiniteLoop { pc: 2, word: 1105 })]
+        );
+
+        // Toggles a cell between two values, which repeats after two iterations
+        assert_eq!(
+            run("1002,9,-1,9,1105,1,0,99,0,1", budget),
+            vec![Err(CpuError::InfiniteLoop { pc: 0, word: 1002 })]
+        );
+
+        // Counting down terminates, even though the pc keeps revisiting the same addresses
+        let countdown = "1001,9,-1,9,1005,9,0,99,0,50";
+        let mut cpu = CPU::<i64>::from_source(countdown);
+        cpu.set_budget(budget);
+        assert_eq!(cpu.try_outputs_with(|| 0).collect::<Vec<_>>(), vec![]);
+        assert_eq!(cpu.executed_instructions(), Some(101));
+
+        // Fingerprinting only looks at stored cells, even after a write 2^40 words away
+        let far_loop = "109,1099511627776,21101,1,0,1,104,7,1105,1,8";
+        let mut cpu = CPU::with_memory(SparseMemory::from(parse_program::<i64>(far_loop)));
+        assert_eq!(cpu.try_outputs_with(|| 0).next(), Some(Ok(7)));
+        cpu.set_budget(budget);
+        assert_eq!(
+            cpu.try_outputs_with(|| 0).next(),
+            Some(Err(CpuError::InfiniteLoop { pc: 8, word: 1105 }))
+        );
+    }
+
+    #[test]
+    fn extension_outputs_count_as_io() {
+        for budget in &[
+            Budget { without_io: Some(2), ..Budget::default() },
+            Budget { detect_loops: true, ..Budget::default() },
+        ] {
+            let mut cpu = CPU::<i64>::from_source("114,7,1105,1,0");
+            cpu.register_extension(14, Echo);
+            cpu.set_budget(*budget);
+            assert_eq!(cpu.try_outputs_with(|| 0).take(10).collect::<Vec<_>>(), vec![Ok(7); 10]);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use super::memory::{Memory, MAX_GAP};
+use super::{CpuError, Instruction, Word, CPU};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub invalidations: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct DecodeCache<T> {
+    entries: Vec<Option<Instruction<T>>>,
+    // Instructions far past the others, so a jump 2^40 words away doesn't allocate everything in
+    // between
+    far: HashMap<usize, Instruction<T>>,
+    stats: CacheStats,
+}
+
+impl<T> DecodeCache<T> {
+    pub(super) fn new() -> DecodeCache<T> {
+        DecodeCache { entries: Vec::new(), far: HashMap::new(), stats: CacheStats::default() }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.entries.clear();
+        self.far.clear();
+    }
+}
+
+impl<T: Word> DecodeCache<T> {
+    fn get(&mut self, pc: usize) -> Option<Instruction<T>> {
+        let instruction = match self.entries.get(pc) {
+            Some(Some(instruction)) => Some(instruction.clone()),
+            _ => self.far.get(&pc).cloned(),
+        };
+        if instruction.is_some() {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        instruction
+    }
+
+    fn insert(&mut self, pc: usize, instruction: Instruction<T>) {
+        if pc >= self.entries.len() + MAX_GAP {
+            self.far.insert(pc, instruction);
+            return;
+        }
+        if self.entries.len() <= pc {
+            self.entries.resize_with(pc + 1, Default::default);
+        }
+        self.entries[pc] = Some(instruction);
+    }
+
+    // Only instructions whose words actually cover the written address are dropped, so data
+    // stored right after an instruction doesn't force it to be decoded again.
+    pub(super) fn invalidate(&mut self, address: usize, longest: usize) {
+        for start in address.saturating_sub(longest - 1)..=address {
+            let covers = |instruction: &Instruction<T>| start + instruction.len() > address;
+            if let Some(entry @ Some(_)) = self.entries.get_mut(start) {
+                if entry.as_ref().is_some_and(covers) {
+                    *entry = None;
+                    self.stats.invalidations += 1;
+                }
+            } else if self.far.get(&start).is_some_and(covers) {
+                self.far.remove(&start);
+                self.stats.invalidations += 1;
+            }
+        }
+    }
+}
+
+impl<T, M> CPU<T, M> {
+    pub fn enable_decode_cache(&mut self) {
+        if self.mmu.cache.is_none() {
+            self.mmu.cache = Some(DecodeCache::new());
+        }
+    }
+
+    pub fn disable_decode_cache(&mut self) {
+        self.mmu.cache = None;
+    }
+
+    pub fn decode_cache_stats(&self) -> Option<CacheStats> {
+        self.mmu.cache.as_ref().map(|cache| cache.stats)
+    }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    pub(super) fn next_instruction(&mut self) -> Result<Instruction<T>, CpuError<T>> {
+        let pc = self.pc;
+        let cached = self.mmu.cache.as_mut().and_then(|cache| cache.get(pc));
+        let instruction = match cached {
+            Some(instruction) => instruction,
+            None => {
+                let instruction = self.try_fetch_instruction()?;
+                if let Some(cache) = &mut self.mmu.cache {
+                    cache.insert(pc, instruction.clone());
+                }
+                instruction
+            }
+        };
+        if self.limits.is_some() {
+            self.check_limits()?;
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.record(pc, &instruction);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, &instruction);
+        }
+        Ok(instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::memory::SparseMemory;
+    use crate::utils::intcode::parse_program;
+    use crate::utils::intcode::resume::Status;
+    use num::BigInt;
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    const BOOST: &str = include_str!("../../day09/input");
+
+    #[test]
+    fn cached_execution_matches() {
+        let mut cpu = CPU::<i64>::from_source(BOOST);
+        cpu.enable_decode_cache();
+        assert_eq!(cpu.outputs_with(|| 2).last(), Some(87571));
+
+        let stats = cpu.decode_cache_stats().unwrap();
+        assert!(stats.hits > 100 * stats.misses, "{:?}", stats);
+    }
+
+    #[test]
+    fn writes_invalidate_cached_instructions() {
+        // Outputs 1, then rewrites the immediate of the WRT at 0 and jumps back to it
+        let mut cpu = CPU::<i64>::from_source("104,1,1101,0,7,1,1105,1,0");
+        cpu.enable_decode_cache();
+        assert_eq!(cpu.outputs().take(2).collect::<Vec<_>>(), vec![1, 7]);
+        assert_eq!(cpu.decode_cache_stats().unwrap().invalidations, 1);
+
+        // Counts down a value stored right after the initial jump, which stays cached
+        let mut cpu = CPU::<i64>::from_source("1105,1,4,3,1001,3,-1,3,4,3,1005,3,4,99");
+        cpu.enable_decode_cache();
+        assert_eq!(cpu.outputs().collect::<Vec<_>>(), vec![2, 1, 0]);
+        assert_eq!(cpu.decode_cache_stats().unwrap().invalidations, 0);
+    }
+
+    #[test]
+    fn far_jumps_stay_cheap() {
+        // Stores a BRK 2^40 words away and jumps to it
+        let far = "109,1099511627776,21101,99,0,0,1106,0,1099511627776";
+        let mut cpu = CPU::with_memory(SparseMemory::from(parse_program::<i64>(far)));
+        cpu.enable_decode_cache();
+        assert_eq!(cpu.try_run(), Ok(Status::Halted));
+        assert_eq!(cpu.decode_cache_stats().unwrap().misses, 4);
+    }
+
+    // Decoding is only a few memory reads, so the cache doesn't save much: about 1.4x with i64
+    // words and 2x with BigInt words, where decoding clones every operand. It pays off for
+    // long running loops over big words, not for short programs.
+    // cargo test --release decode_cache_speedup -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn decode_cache_speedup() {
+        fn time<T: Word + FromStr>(cached: bool, runs: usize) -> Duration {
+            let start = Instant::now();
+            for _ in 0..runs {
+                let mut cpu = CPU::<T>::from_source(BOOST);
+                if cached {
+                    cpu.enable_decode_cache();
+                }
+                assert_eq!(cpu.outputs_with(|| T::from_i64(2).unwrap()).last(), T::from_i64(87571));
+            }
+            start.elapsed()
+        }
+
+        for (name, uncached, cached) in [
+            ("i64", time::<i64>(false, 10), time::<i64>(true, 10)),
+            ("BigInt", time::<BigInt>(false, 3), time::<BigInt>(true, 3)),
+        ] {
+            let speedup = uncached.as_secs_f64() / cached.as_secs_f64();
+            println!(
+                "{}: uncached: {:?}, cached: {:?}, speedup: {:.2}x",
+                name, uncached, cached, speedup
+            );
+            assert!(speedup > 1.0, "the cache slowed {} words down to {:.2}x", name, speedup);
+        }
+    }
+}
@@ -0,0 +1,736 @@
+use std::collections::HashMap;
+use std::iter::from_fn;
+
+use super::extension::{Effect, Extensions};
+use super::memory::{Memory, MAX_GAP};
+use super::{Arithmetic, CpuError, Instruction, Parameter, Word, CPU};
+
+enum Step<T> {
+    Next(usize),
+    Output(T, usize),
+    Input(usize, usize),
+    Halt,
+}
+
+// A parameter with its mode resolved
+enum Operand<T> {
+    Value(T),
+    Cell(usize),
+    Frame(isize),
+}
+
+impl<T> From<Parameter<T>> for Operand<T> {
+    fn from(param: Parameter<T>) -> Operand<T> {
+        match param {
+            Parameter::Immediate(value) => Operand::Value(value),
+            Parameter::Indexed(index) => Operand::Cell(index),
+            Parameter::Relative(offset) => Operand::Frame(offset),
+        }
+    }
+}
+
+enum Op<T> {
+    Add(Operand<T>, Operand<T>, Operand<T>),
+    Multiply(Operand<T>, Operand<T>, Operand<T>),
+    LessThan(Operand<T>, Operand<T>, Operand<T>),
+    Equals(Operand<T>, Operand<T>, Operand<T>),
+    JumpIfTrue(Operand<T>, Operand<T>),
+    JumpIfFalse(Operand<T>, Operand<T>),
+    Read(Operand<T>),
+    Write(Operand<T>),
+    RelativeBaseOffset(Operand<T>),
+    Break,
+    // Extensions, and instructions whose opcode has been overwritten, are decoded on every visit
+    Interpreted,
+}
+
+impl<T: Word> Op<T> {
+    fn translate(instruction: Instruction<T>) -> Op<T> {
+        match instruction {
+            Instruction::Add(a, b, c) => Op::Add(a.into(), b.into(), c.into()),
+            Instruction::Multiply(a, b, c) => Op::Multiply(a.into(), b.into(), c.into()),
+            Instruction::LessThan(a, b, c) => Op::LessThan(a.into(), b.into(), c.into()),
+            Instruction::Equals(a, b, c) => Op::Equals(a.into(), b.into(), c.into()),
+            Instruction::JumpIfTrue(cond, target) => Op::JumpIfTrue(cond.into(), target.into()),
+            Instruction::JumpIfFalse(cond, target) => Op::JumpIfFalse(cond.into(), target.into()),
+            Instruction::Read(dest) => Op::Read(dest.into()),
+            Instruction::Write(param) => Op::Write(param.into()),
+            Instruction::RelativeBaseOffset(param) => Op::RelativeBaseOffset(param.into()),
+            Instruction::Break => Op::Break,
+            Instruction::Extended { .. } => Op::Interpreted,
+        }
+    }
+
+    fn operand_mut(&mut self, index: usize) -> Option<&mut Operand<T>> {
+        match (self, index) {
+            (Op::Add(a, ..), 0) | (Op::Multiply(a, ..), 0) => Some(a),
+            (Op::LessThan(a, ..), 0) | (Op::Equals(a, ..), 0) => Some(a),
+            (Op::Add(_, b, _), 1) | (Op::Multiply(_, b, _), 1) => Some(b),
+            (Op::LessThan(_, b, _), 1) | (Op::Equals(_, b, _), 1) => Some(b),
+            (Op::Add(.., c), 2) | (Op::Multiply(.., c), 2) => Some(c),
+            (Op::LessThan(.., c), 2) | (Op::Equals(.., c), 2) => Some(c),
+            (Op::JumpIfTrue(cond, _), 0) | (Op::JumpIfFalse(cond, _), 0) => Some(cond),
+            (Op::JumpIfTrue(_, target), 1) | (Op::JumpIfFalse(_, target), 1) => Some(target),
+            (Op::Read(param), 0) | (Op::Write(param), 0) => Some(param),
+            (Op::RelativeBaseOffset(param), 0) => Some(param),
+            _ => None,
+        }
+    }
+
+    // Applies a write to word `word` of the instruction. Only a new operand keeps the
+    // translation, anything that wouldn't decode to the same instruction is decoded again.
+    fn patch(&mut self, word: usize, value: T) {
+        let patched = match word.checked_sub(1).and_then(|index| self.operand_mut(index)) {
+            Some(Operand::Value(operand)) => {
+                *operand = value;
+                true
+            }
+            Some(Operand::Cell(index)) => value.to_usize().map(|value| *index = value).is_some(),
+            Some(Operand::Frame(offset)) => value.to_isize().map(|value| *offset = value).is_some(),
+            None => false,
+        };
+        if !patched {
+            *self = Op::Interpreted;
+        }
+    }
+}
+
+struct Translated<T> {
+    pc: usize,
+    op: Op<T>,
+}
+
+// Straight-line code from where it was first entered up to an unconditional jump, with `next`
+// the address after its last instruction
+struct Run<T> {
+    ops: Vec<Translated<T>>,
+    next: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Location {
+    run: usize,
+    index: usize,
+}
+
+// The instruction a word of code belongs to, and which of its words it is
+#[derive(Clone, Copy)]
+struct Cover {
+    location: Location,
+    word: usize,
+}
+
+// Dense near the start of memory and a map past that, like the memory itself, so code 2^40 words
+// away doesn't allocate everything in between
+struct Table<V> {
+    dense: Vec<Option<V>>,
+    far: HashMap<usize, V>,
+}
+
+impl<V> Table<V> {
+    fn new() -> Table<V> {
+        Table { dense: Vec::new(), far: HashMap::new() }
+    }
+
+    #[inline]
+    fn get(&self, index: usize) -> Option<&V> {
+        match self.dense.get(index) {
+            Some(entry) => entry.as_ref(),
+            None if self.far.is_empty() => None,
+            None => self.far.get(&index),
+        }
+    }
+
+    fn insert(&mut self, index: usize, value: V) {
+        if index >= self.dense.len() && index - self.dense.len() > MAX_GAP {
+            self.far.insert(index, value);
+            return;
+        }
+        if index >= self.dense.len() {
+            self.dense.resize_with(index + 1, || None);
+            let len = self.dense.len();
+            for (address, value) in std::mem::take(&mut self.far) {
+                if address < len {
+                    self.dense[address] = Some(value);
+                } else {
+                    self.far.insert(address, value);
+                }
+            }
+        }
+        self.dense[index] = Some(value);
+    }
+}
+
+struct Machine<T> {
+    memory: Vec<T>,
+    // Cells far past the end of memory, so a write 2^40 words away doesn't allocate everything
+    // in between
+    far: HashMap<usize, T>,
+    rbo: isize,
+    limit: Option<usize>,
+    arithmetic: Arithmetic,
+    extensions: Extensions<T>,
+    covers: Table<Cover>,
+    modified: Vec<usize>,
+    budget: Option<usize>,
+    executed: usize,
+    exit_code: Option<T>,
+}
+
+impl<T: Word> Machine<T> {
+    #[inline(always)]
+    fn get(&self, index: usize) -> T {
+        match self.memory.get(index) {
+            Some(value) => value.clone(),
+            None if self.far.is_empty() => T::zero(),
+            None => self.far.get(&index).cloned().unwrap_or_else(T::zero),
+        }
+    }
+
+    #[inline(always)]
+    fn set(&mut self, index: usize, value: T) {
+        if index < self.memory.len() {
+            self.memory[index] = value;
+        } else if index - self.memory.len() > MAX_GAP {
+            self.far.insert(index, value);
+        } else {
+            self.memory.resize_with(index + 1, T::zero);
+            let len = self.memory.len();
+            for (&address, value) in self.far.iter().filter(|(&address, _)| address < len) {
+                self.memory[address] = value.clone();
+            }
+            self.far.retain(|&address, _| address >= len);
+            self.memory[index] = value;
+        }
+        if self.covers.get(index).is_some() {
+            self.modified.push(index);
+        }
+    }
+
+    #[inline(always)]
+    fn address(&self, operand: &Operand<T>, pc: usize) -> Result<usize, CpuError<T>> {
+        let address = match *operand {
+            Operand::Cell(index) => Some(index),
+            Operand::Frame(offset) => match self.rbo.checked_add(offset) {
+                Some(address) if address >= 0 => Some(address as usize),
+                _ => None,
+            },
+            Operand::Value(_) => None,
+        };
+        match (address, self.limit) {
+            (Some(address), Some(limit)) if address < limit => Ok(address),
+            (Some(address), None) => Ok(address),
+            _ => Err(self.invalid(operand, pc)),
+        }
+    }
+
+    #[cold]
+    fn invalid(&self, operand: &Operand<T>, pc: usize) -> CpuError<T> {
+        let word = self.get(pc);
+        match *operand {
+            Operand::Value(_) => CpuError::WriteToImmediate { pc, word },
+            Operand::Cell(address) => CpuError::AddressOutOfBounds { pc, word, address },
+            Operand::Frame(offset) => match self.rbo.checked_add(offset) {
+                Some(address) if address >= 0 => {
+                    CpuError::AddressOutOfBounds { pc, word, address: address as usize }
+                }
+                Some(address) => CpuError::NegativeAddress { pc, word, address },
+                None => CpuError::Overflow { pc, word },
+            },
+        }
+    }
+
+    #[inline(always)]
+    fn load(&self, operand: &Operand<T>, pc: usize) -> Result<T, CpuError<T>> {
+        match operand {
+            Operand::Value(value) => Ok(value.clone()),
+            operand => self.address(operand, pc).map(|address| self.get(address)),
+        }
+    }
+
+    #[inline(always)]
+    fn store(&mut self, dest: &Operand<T>, value: Option<T>, pc: usize) -> Result<(), CpuError<T>> {
+        let value = value.ok_or_else(|| self.overflow(pc))?;
+        let dest = self.address(dest, pc)?;
+        self.set(dest, value);
+        Ok(())
+    }
+
+    #[inline]
+    fn flag(value: bool) -> Option<T> {
+        Some(if value { T::one() } else { T::zero() })
+    }
+
+    #[inline(always)]
+    fn target(&self, operand: &Operand<T>, pc: usize) -> Result<usize, CpuError<T>> {
+        let target = self.load(operand, pc)?;
+        match (target.to_usize(), self.limit) {
+            (Some(address), Some(limit)) if address < limit => Ok(address),
+            (Some(address), None) => Ok(address),
+            _ => Err(CpuError::JumpOutOfRange { pc, word: self.get(pc), target }),
+        }
+    }
+
+    #[cold]
+    fn overflow(&self, pc: usize) -> CpuError<T> {
+        CpuError::Overflow { pc, word: self.get(pc) }
+    }
+
+    fn decode(&self, pc: usize) -> Result<Instruction<T>, CpuError<T>> {
+        Instruction::decode_with(|index| self.get(index), pc, &self.extensions)
+    }
+
+    #[inline(always)]
+    // Returns `None` to carry on with the next instruction of the run
+    fn execute(&mut self, op: &Op<T>, pc: usize) -> Result<Option<Step<T>>, CpuError<T>> {
+        let step = match op {
+            Op::Add(a, b, c) => {
+                let value = self.arithmetic.add(&self.load(a, pc)?, &self.load(b, pc)?);
+                self.store(c, value, pc)?;
+                None
+            }
+            Op::Multiply(a, b, c) => {
+                let value = self.arithmetic.mul(&self.load(a, pc)?, &self.load(b, pc)?);
+                self.store(c, value, pc)?;
+                None
+            }
+            Op::LessThan(a, b, c) => {
+                let value = Self::flag(self.load(a, pc)? < self.load(b, pc)?);
+                self.store(c, value, pc)?;
+                None
+            }
+            Op::Equals(a, b, c) => {
+                let value = Self::flag(self.load(a, pc)? == self.load(b, pc)?);
+                self.store(c, value, pc)?;
+                None
+            }
+            Op::JumpIfTrue(cond, target) if !self.load(cond, pc)?.is_zero() => {
+                Some(Step::Next(self.target(target, pc)?))
+            }
+            Op::JumpIfFalse(cond, target) if self.load(cond, pc)?.is_zero() => {
+                Some(Step::Next(self.target(target, pc)?))
+            }
+            Op::JumpIfTrue(..) | Op::JumpIfFalse(..) => None,
+            Op::Read(dest) => Some(Step::Input(self.address(dest, pc)?, pc + 2)),
+            Op::Write(param) => Some(Step::Output(self.load(param, pc)?, pc + 2)),
+            Op::RelativeBaseOffset(param) => {
+                let offset = self.load(param, pc)?.to_isize();
+                self.rbo = offset
+                    .and_then(|offset| self.rbo.checked_add(offset))
+                    .ok_or_else(|| self.overflow(pc))?;
+                None
+            }
+            Op::Break => Some(Step::Halt),
+            Op::Interpreted => Some(self.interpret(pc)?),
+        };
+        Ok(step)
+    }
+
+    #[inline(never)]
+    fn interpret(&mut self, pc: usize) -> Result<Step<T>, CpuError<T>> {
+        let (opcode, params) = match self.decode(pc)? {
+            Instruction::Extended { opcode, params, .. } => (opcode, params),
+            instruction => {
+                let next = pc + instruction.len();
+                let step = self.execute(&Op::translate(instruction), pc)?;
+                return Ok(step.unwrap_or(Step::Next(next)));
+            }
+        };
+        let next = pc + 1 + params.len();
+        let extension = self.extensions.get(opcode).cloned().unwrap();
+        let mut params: Vec<Operand<T>> = params.into_iter().map(Operand::from).collect();
+        let dest = if extension.stores() { params.pop() } else { None };
+        let args =
+            params.iter().map(|param| self.load(param, pc)).collect::<Result<Vec<_>, _>>()?;
+        let failed = || CpuError::ExtensionFailed { pc, word: self.get(pc) };
+        let step = match extension.execute(&args).ok_or_else(failed)? {
+            Effect::Next => Step::Next(next),
+            Effect::Store(value) => {
+                let dest = dest.ok_or_else(failed)?;
+                self.store(&dest, Some(value), pc)?;
+                Step::Next(next)
+            }
+            Effect::Jump(target) => Step::Next(self.target(&Operand::Value(target), pc)?),
+            Effect::Output(value) => Step::Output(value, next),
+            Effect::Exit(code) => {
+                self.exit_code = Some(code);
+                Step::Halt
+            }
+        };
+        Ok(step)
+    }
+}
+
+// Code is translated the first time it's reached, into runs of straight-line instructions with
+// their modes resolved that execute in a loop until a jump is taken, I/O happens or the code is
+// written to. A write to an operand patches the translated instruction in place.
+pub struct CompiledCpu<T> {
+    pc: usize,
+    machine: Machine<T>,
+    runs: Vec<Run<T>>,
+    entries: Table<Location>,
+}
+
+impl<T: Word + 'static> CompiledCpu<T> {
+    fn translate(&mut self, pc: usize) -> Result<Location, CpuError<T>> {
+        let run = self.runs.len();
+        let mut ops = Vec::new();
+        let mut address = pc;
+        loop {
+            let instruction = match self.machine.decode(address) {
+                Ok(instruction) => instruction,
+                Err(err) if ops.is_empty() => return Err(err),
+                // Left for when the run actually gets there
+                Err(_) => break,
+            };
+            let len = instruction.len();
+            let location = Location { run, index: ops.len() };
+            let covered = |word| self.machine.covers.get(word).is_some();
+            if (address..address + len).any(covered) {
+                // Jumping into the middle of translated code, which only gets interpreted
+                if ops.is_empty() {
+                    self.entries.insert(address, location);
+                    ops.push(Translated { pc: address, op: Op::Interpreted });
+                    address += len;
+                }
+                break;
+            }
+
+            let jumps = match &instruction {
+                Instruction::JumpIfTrue(Parameter::Immediate(cond), _) => !cond.is_zero(),
+                Instruction::JumpIfFalse(Parameter::Immediate(cond), _) => cond.is_zero(),
+                Instruction::Break => true,
+                _ => false,
+            };
+            for word in 0..len {
+                self.machine.covers.insert(address + word, Cover { location, word });
+            }
+            self.entries.insert(address, location);
+            ops.push(Translated { pc: address, op: Op::translate(instruction) });
+            address += len;
+            if jumps {
+                break;
+            }
+        }
+        self.runs.push(Run { ops, next: address });
+        Ok(Location { run, index: 0 })
+    }
+
+    fn patch(&mut self) {
+        while let Some(address) = self.machine.modified.pop() {
+            let Cover { location, word } = *self.machine.covers.get(address).unwrap();
+            let value = self.machine.get(address);
+            self.runs[location.run].ops[location.index].op.patch(word, value);
+        }
+    }
+
+    // Runs the ops from `index` up to `end`, leaving `index` after the last one executed
+    fn execute(
+        &mut self,
+        run: usize,
+        index: &mut usize,
+        end: usize,
+    ) -> Result<Step<T>, CpuError<T>> {
+        while *index < end {
+            let Translated { pc, op } = &self.runs[run].ops[*index];
+            let pc = *pc;
+            *index += 1;
+            let step = self.machine.execute(op, pc)?;
+            if !self.machine.modified.is_empty() {
+                self.patch();
+            }
+            if let Some(step) = step {
+                self.pc = pc;
+                return Ok(step);
+            }
+        }
+        let Run { ops, next } = &self.runs[run];
+        Ok(Step::Next(ops.get(end).map_or(*next, |op| op.pc)))
+    }
+
+    // Follows jumps from run to run until there's I/O or the program stops
+    fn step(&mut self) -> Result<Step<T>, CpuError<T>> {
+        loop {
+            let Location { run, index } = match self.entries.get(self.pc) {
+                Some(&location) => location,
+                None => self.translate(self.pc)?,
+            };
+
+            // The budget is charged for the whole run up front, and counted after decoding like
+            // the interpreter does, so both report the same error first
+            let len = self.runs[run].ops.len();
+            let end = match self.machine.budget {
+                Some(budget) if budget == self.machine.executed => {
+                    self.machine.decode(self.pc)?;
+                    let word = self.machine.get(self.pc);
+                    return Err(CpuError::BudgetExhausted { pc: self.pc, word });
+                }
+                Some(budget) => len.min(index.saturating_add(budget - self.machine.executed)),
+                None => len,
+            };
+            let mut executed = index;
+            let step = self.execute(run, &mut executed, end);
+            self.machine.executed += executed - index;
+            match step? {
+                Step::Next(next) => self.pc = next,
+                step => return Ok(step),
+            }
+        }
+    }
+
+    fn next_output(&mut self, get_input: &mut impl FnMut() -> T) -> Option<Result<T, CpuError<T>>> {
+        loop {
+            match self.step() {
+                Ok(Step::Next(next)) => self.pc = next,
+                Ok(Step::Output(value, next)) => {
+                    self.pc = next;
+                    return Some(Ok(value));
+                }
+                Ok(Step::Input(dest, next)) => {
+                    self.machine.set(dest, get_input());
+                    self.patch();
+                    self.pc = next;
+                }
+                Ok(Step::Halt) => return None,
+                Err(err) => {
+                    self.pc = err.pc();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    // Stops with `BudgetExhausted` once this many instructions have been executed
+    pub fn set_budget(&mut self, instructions: Option<usize>) {
+        self.machine.budget = instructions;
+        self.machine.executed = 0;
+    }
+
+    pub fn get(&self, index: usize) -> T {
+        self.machine.get(index)
+    }
+
+    pub fn exit_code(&self) -> Option<&T> {
+        self.machine.exit_code.as_ref()
+    }
+
+    pub fn outputs(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.outputs_with(|| panic!("No input provided!"))
+    }
+
+    pub fn outputs_with<'a>(
+        &'a mut self,
+        mut get_input: impl FnMut() -> T + 'a,
+    ) -> impl Iterator<Item = T> + 'a {
+        from_fn(move || match self.next_output(&mut get_input)? {
+            Ok(value) => Some(value),
+            Err(CpuError::InvalidOpcode { word, .. }) if word.is_negative() => None,
+            Err(err) => panic!("{}", err),
+        })
+    }
+
+    pub fn try_outputs_with<'a>(
+        &'a mut self,
+        mut get_input: impl FnMut() -> T + 'a,
+    ) -> impl Iterator<Item = Result<T, CpuError<T>>> + 'a {
+        let mut failed = false;
+        from_fn(move || {
+            if failed {
+                return None;
+            }
+            let result = self.next_output(&mut get_input);
+            failed = matches!(result, Some(Err(_)));
+            result
+        })
+    }
+}
+
+impl<T: Word + 'static, M: Memory<T>> CPU<T, M> {
+    pub fn compile(&self) -> CompiledCpu<T> {
+        let machine = Machine {
+            memory: Vec::new(),
+            far: HashMap::new(),
+            rbo: self.rbo,
+            limit: self.mmu.limit(),
+            arithmetic: self.arithmetic,
+            extensions: self.mmu.extensions.clone(),
+            covers: Table::new(),
+            modified: Vec::new(),
+            budget: None,
+            executed: 0,
+            exit_code: self.exit_code.clone(),
+        };
+        let mut compiled =
+            CompiledCpu { pc: self.pc, machine, runs: Vec::new(), entries: Table::new() };
+        for (address, value) in self.mmu.cells().filter(|(_, value)| !value.is_zero()) {
+            compiled.machine.set(address, value);
+        }
+
+        compiled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::memory::SparseMemory;
+    use crate::utils::intcode::parse_program;
+    use itertools::Itertools;
+    use std::cell::{Cell, RefCell};
+    use std::time::Instant;
+
+    type Outputs = Vec<Result<i64, CpuError<i64>>>;
+
+    // Runs a program on the interpreter and the compiled version, deriving every input from the
+    // outputs seen so far so that both see the same inputs
+    fn run_both(source: &str, patch: &[(usize, i64)], input: fn(&[i64]) -> i64) -> Outputs {
+        let mut cpu = CPU::<i64>::from_source(source);
+        for &(address, value) in patch {
+            *cpu.mmu.get_mut(address) = value;
+        }
+        let mut compiled = cpu.compile();
+
+        let run = |outputs: &mut dyn Iterator<Item = Result<i64, CpuError<i64>>>,
+                   seen: &RefCell<Vec<i64>>| {
+            let mut results = Vec::new();
+            for output in outputs.take(20_000) {
+                if let Ok(value) = output {
+                    seen.borrow_mut().push(value);
+                }
+                results.push(output);
+            }
+            results
+        };
+
+        let seen = RefCell::new(Vec::new());
+        let expected = run(&mut cpu.try_outputs_with(|| input(&seen.borrow())), &seen);
+        let seen = RefCell::new(Vec::new());
+        let actual = run(&mut compiled.try_outputs_with(|| input(&seen.borrow())), &seen);
+        assert_eq!(actual, expected);
+        expected
+    }
+
+    // Plays day 13's arcade game by keeping the paddle under the ball, returning the final score
+    fn play(outputs: impl Iterator<Item = i64>, ball: &Cell<i64>, paddle: &Cell<i64>) -> i64 {
+        let mut score = 0;
+        for output in outputs.tuples() {
+            match output {
+                (-1, 0, value) => score = value,
+                (x, _, 3) => paddle.set(x),
+                (x, _, 4) => ball.set(x),
+                _ => {}
+            }
+        }
+        score
+    }
+
+    fn arcade() -> CPU<i64> {
+        let mut cpu = CPU::from_source(include_str!("../../day13/input"));
+        *cpu.mmu.get_mut(0) = 2;
+        cpu
+    }
+
+    #[test]
+    fn matches_interpreter_on_puzzle_inputs() {
+        let day02 = include_str!("../../day02/input");
+        run_both(day02, &[(1, 12), (2, 2)], |_| 0);
+
+        let day05 = include_str!("../../day05/input");
+        assert_eq!(run_both(day05, &[], |_| 1).last(), Some(&Ok(11_193_703)));
+        assert_eq!(run_both(day05, &[], |_| 5), vec![Ok(12_410_607)]);
+
+        let day07 = include_str!("../../day07/input");
+        run_both(day07, &[], |outputs| [4, 0, 3, 2, 1][outputs.len() % 5]);
+
+        let day09 = include_str!("../../day09/input");
+        assert_eq!(run_both(day09, &[], |_| 2), vec![Ok(87571)]);
+
+        let day11 = include_str!("../../day11/input");
+        run_both(day11, &[], |outputs| outputs.len() as i64 / 2 % 2);
+
+        let day13 = include_str!("../../day13/input");
+        run_both(day13, &[(0, 2)], |outputs| outputs.len() as i64 % 3 - 1);
+
+        let day15 = include_str!("../../day15/input");
+        run_both(day15, &[], |outputs| outputs.iter().sum::<i64>() % 4 + 1);
+
+        let day17 = include_str!("../../day17/input");
+        run_both(day17, &[], |_| 0);
+        run_both(day17, &[(0, 2)], |outputs| b"A\nL,4\nL,4\nL,4\nn\n"[outputs.len() % 16].into());
+
+        let (ball, paddle) = (Cell::new(0i64), Cell::new(0));
+        let joystick = || (ball.get() - paddle.get()).signum();
+        assert_eq!(play(arcade().compile().outputs_with(joystick), &ball, &paddle), 15706);
+    }
+
+    #[test]
+    fn self_modifying_code_is_retranslated() {
+        // Outputs 1, then rewrites the immediate of the WRT at 0 and jumps back to it
+        let mut compiled = CPU::<i64>::from_source("104,1,1101,0,7,1,1105,1,0").compile();
+        assert_eq!(compiled.outputs().take(3).collect::<Vec<_>>(), vec![1, 7, 7]);
+
+        // Reads an opcode into the word that gets executed next
+        let mut compiled = CPU::<i64>::from_source("3,2,0,42,99").compile();
+        assert_eq!(compiled.outputs_with(|| 104).collect::<Vec<_>>(), vec![42]);
+
+        // Overwrites the BRK it was compiled with by a WRT IN(0)
+        let mut compiled = CPU::<i64>::from_source("1101,2,2,4,99").compile();
+        assert_eq!(
+            compiled.try_outputs_with(|| 0).collect::<Vec<_>>(),
+            vec![Ok(1101), Err(CpuError::InvalidOpcode { pc: 6, word: 0 })]
+        );
+
+        // Patches the address of the WRT at 4 to one that no longer decodes
+        assert_eq!(
+            run_both("1101,-5,0,5,4,0,99", &[], |_| 0),
+            vec![Err(CpuError::NegativeAddress { pc: 4, word: 4, address: -5 })]
+        );
+    }
+
+    #[test]
+    fn far_jumps_stay_cheap() {
+        // Stores a BRK 2^40 words away and jumps to it
+        let far = "109,1099511627776,21101,99,0,0,1106,0,1099511627776";
+        let cpu = CPU::with_memory(SparseMemory::from(parse_program::<i64>(far)));
+        let mut compiled = cpu.compile();
+        assert_eq!(compiled.try_outputs_with(|| 0).collect::<Vec<_>>(), vec![]);
+        assert_eq!(compiled.get(1 << 40), 99);
+        assert_eq!(compiled.pc(), 1 << 40);
+    }
+
+    // Every instruction still goes through a match on its op and its operands' modes, so on
+    // puzzle inputs this comes out at around 5x rather than anything like native speed.
+    // cargo test --release compiled_speedup -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn compiled_speedup() {
+        let cpu = arcade();
+        let (ball, paddle) = (Cell::new(0i64), Cell::new(0));
+        let joystick = || (ball.get() - paddle.get()).signum();
+
+        // Best of a few runs, since a single one is easily thrown off by a noisy machine
+        let time = |run: &dyn Fn() -> i64| {
+            (0..5)
+                .map(|_| {
+                    let start = Instant::now();
+                    assert_eq!(run(), 15706);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        };
+        let interpreted = time(&|| play(cpu.clone().outputs_with(joystick), &ball, &paddle));
+        let compiled = time(&|| play(cpu.compile().outputs_with(joystick), &ball, &paddle));
+
+        let speedup = interpreted.as_secs_f64() / compiled.as_secs_f64();
+        println!(
+            "interpreted: {:?}, compiled: {:?}, speedup: {:.2}x",
+            interpreted, compiled, speedup
+        );
+        assert!(speedup > 4.0, "compiled code is only {:.2}x faster", speedup);
+    }
+}
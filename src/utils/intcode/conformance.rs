@@ -0,0 +1,293 @@
+use std::str::FromStr;
+
+use num::BigInt;
+
+use super::memory::{BoundedMemory, DenseMemory};
+use super::{parse_program, Arithmetic, CpuError, Word, CPU};
+
+type Outputs<T> = Vec<Result<T, CpuError<T>>>;
+
+type Operation = fn(i64, i64) -> i64;
+
+const RBO: i64 = 10;
+
+// Runs a program on the interpreter and checks that the decode cache and the compiled backend
+// produce the same outputs and memory
+fn execute<T: Word + 'static>(memory: Vec<T>, inputs: &[T]) -> (CPU<T>, Outputs<T>) {
+    let mut cpu = CPU::new(memory);
+    let mut cached = cpu.clone();
+    cached.enable_decode_cache();
+    let mut compiled = cpu.compile();
+
+    let mut feed = inputs.iter().cycle().cloned();
+    let outputs: Outputs<T> =
+        cpu.try_outputs_with(move || feed.next().expect("No input provided!")).collect();
+
+    let mut feed = inputs.iter().cycle().cloned();
+    let cached_outputs: Outputs<T> =
+        cached.try_outputs_with(move || feed.next().expect("No input provided!")).collect();
+    assert_eq!(cached_outputs, outputs, "decode cache");
+
+    let mut feed = inputs.iter().cycle().cloned();
+    let compiled_outputs: Outputs<T> =
+        compiled.try_outputs_with(move || feed.next().expect("No input provided!")).collect();
+    assert_eq!(compiled_outputs, outputs, "compiled");
+
+    for address in 0..cpu.mmu.len() {
+        assert_eq!(cached.mmu.get(address), cpu.mmu.get(address), "decode cache at {}", address);
+        assert_eq!(compiled.get(address), cpu.mmu.get(address), "compiled at {}", address);
+    }
+    (cpu, outputs)
+}
+
+fn run<T: Word + FromStr + 'static>(source: &str, inputs: &[T]) -> Outputs<T> {
+    execute(parse_program(source), inputs).1
+}
+
+fn opcode(opcode: i64, modes: &[i64]) -> i64 {
+    modes
+        .iter()
+        .enumerate()
+        .fold(opcode, |word, (index, mode)| word + mode * 10i64.pow(index as u32 + 2))
+}
+
+// The parameter reaching `address` in the given mode, or holding `value` in immediate mode
+fn param(mode: i64, address: i64, value: i64) -> i64 {
+    match mode {
+        0 => address,
+        1 => value,
+        _ => address - RBO,
+    }
+}
+
+// Every program below starts by setting the relative base to RBO, and keeps its data from
+// address 20 on
+fn program(code: &[i64], data: &[i64]) -> Vec<i64> {
+    let mut program = vec![109, RBO];
+    program.extend(code);
+    program.resize(20, 0);
+    program.extend(data);
+    program
+}
+
+#[test]
+fn binary_operations_in_every_mode() {
+    let operations: [(i64, Operation); 4] = [
+        (1, |a, b| a + b),
+        (2, |a, b| a * b),
+        (7, |a, b| (a < b) as i64),
+        (8, |a, b| (a == b) as i64),
+    ];
+
+    for &(code, apply) in &operations {
+        for &(a, b) in &[(3, 5), (5, 5), (-4, 3), (0, -7)] {
+            for a_mode in 0..3 {
+                for b_mode in 0..3 {
+                    for &c_mode in &[0, 2] {
+                        let word = opcode(code, &[a_mode, b_mode, c_mode]);
+                        let instruction = [
+                            word,
+                            param(a_mode, 20, a),
+                            param(b_mode, 21, b),
+                            param(c_mode, 22, 0),
+                        ];
+                        let mut code = instruction.to_vec();
+                        code.push(99);
+
+                        let (cpu, outputs) = execute(program(&code, &[a, b, -1]), &[]);
+                        assert_eq!(outputs, vec![]);
+                        assert_eq!(
+                            (cpu.mmu.get(20), cpu.mmu.get(21), cpu.mmu.get(22)),
+                            (a, b, apply(a, b)),
+                            "{:?}",
+                            instruction
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn jumps_in_every_mode() {
+    for &code in &[5, 6] {
+        for &cond in &[0, 1, -3] {
+            for cond_mode in 0..3 {
+                for target_mode in 0..3 {
+                    // Outputs 1 from address 10 when jumping, and 0 when falling through
+                    let word = opcode(code, &[cond_mode, target_mode]);
+                    let jump = [word, param(cond_mode, 20, cond), param(target_mode, 21, 10)];
+                    let mut code_words = jump.to_vec();
+                    code_words.extend(&[104, 0, 99, 0, 0, 104, 1, 99]);
+
+                    let (_, outputs) = execute(program(&code_words, &[cond, 10]), &[]);
+                    let jumps = (cond != 0) == (code == 5);
+                    assert_eq!(outputs, vec![Ok(jumps as i64)], "{:?}", jump);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn io_and_relative_base_in_every_mode() {
+    for &read_mode in &[0, 2] {
+        for write_mode in 0..3 {
+            let read = [opcode(3, &[read_mode]), param(read_mode, 20, 0)];
+            let write = [opcode(4, &[write_mode]), param(write_mode, 20, 42)];
+            let code: Vec<_> = read.iter().chain(&write).chain(&[99]).copied().collect();
+            let (cpu, outputs) = execute(program(&code, &[0]), &[42]);
+            assert_eq!(outputs, vec![Ok(42)], "{:?} {:?}", read, write);
+            assert_eq!(cpu.mmu.get(20), 42);
+        }
+    }
+
+    // Adds 7 to the relative base, then outputs the word the new base points at
+    for mode in 0..3 {
+        let adjust = [opcode(9, &[mode]), param(mode, 20, 7)];
+        let code: Vec<_> = adjust.iter().chain(&[204, 3, 99]).copied().collect();
+        let (_, outputs) = execute(program(&code, &[7]), &[]);
+        assert_eq!(outputs, vec![Ok(7)], "{:?}", adjust);
+    }
+}
+
+#[test]
+fn relative_base_edge_cases() {
+    // A negative base is fine as long as the addresses it produces aren't
+    assert_eq!(run::<i64>("109,-3,204,3,99", &[]), vec![Ok(109)]);
+    assert_eq!(run::<i64>("109,5,109,-5,204,0,99", &[]), vec![Ok(109)]);
+    assert_eq!(
+        run::<i64>("109,-3,204,2,99", &[]),
+        vec![Err(CpuError::NegativeAddress { pc: 2, word: 204, address: -1 })]
+    );
+    assert_eq!(
+        run::<i64>("21101,1,2,-1,99", &[]),
+        vec![Err(CpuError::NegativeAddress { pc: 0, word: 21101, address: -1 })]
+    );
+
+    // Bases and addresses that don't fit in an isize overflow instead of wrapping
+    assert_eq!(
+        run::<i64>("109,9223372036854775807,109,1,99", &[]),
+        vec![Err(CpuError::Overflow { pc: 2, word: 109 })]
+    );
+    assert_eq!(
+        run::<i64>("109,-9223372036854775807,204,-9223372036854775807,99", &[]),
+        vec![Err(CpuError::Overflow { pc: 2, word: 204 })]
+    );
+    assert_eq!(
+        run::<i64>("109,9223372036854775807,21101,1,1,1,99", &[]),
+        vec![Err(CpuError::Overflow { pc: 2, word: 21101 })]
+    );
+
+    // The base can be adjusted by itself, and by values read from memory it points at
+    assert_eq!(run::<i64>("109,7,209,0,204,-10,99,3", &[]), vec![Ok(109)]);
+    assert_eq!(run::<i64>("109,100,203,0,204,0,99", &[-8]), vec![Ok(-8)]);
+}
+
+#[test]
+fn invalid_instructions() {
+    let error = |source| run::<i64>(source, &[0]).pop().unwrap().unwrap_err();
+    assert_eq!(error("11101,1,2,3,99"), CpuError::WriteToImmediate { pc: 0, word: 11101 });
+    assert_eq!(error("103,0,99"), CpuError::WriteToImmediate { pc: 0, word: 103 });
+    assert_eq!(error("11108,1,1,0,99"), CpuError::WriteToImmediate { pc: 0, word: 11108 });
+    assert_eq!(error("304,0,99"), CpuError::InvalidMode { pc: 0, word: 304, mode: 3 });
+    assert_eq!(error("30001,0,0,0,99"), CpuError::InvalidMode { pc: 0, word: 30001, mode: 3 });
+    assert_eq!(error("104,1,98"), CpuError::InvalidOpcode { pc: 2, word: 98 });
+    assert_eq!(error("10"), CpuError::InvalidOpcode { pc: 0, word: 10 });
+    assert_eq!(error("1105,1,-4"), CpuError::JumpOutOfRange { pc: 0, word: 1105, target: -4 });
+
+    // Running off the end of the program reads zeros, which isn't an instruction
+    assert_eq!(error("104,1"), CpuError::InvalidOpcode { pc: 2, word: 0 });
+}
+
+#[test]
+fn memory_past_the_program() {
+    // Writes grow memory, and reads past the end see zeros
+    let (cpu, outputs) = execute(parse_program("1101,2,3,1000,4,1000,4,5000,99"), &[]);
+    assert_eq!(outputs, vec![Ok(5), Ok(0)]);
+    assert_eq!(cpu.mmu.len(), 1001);
+
+    let (cpu, outputs) = execute(parse_program("109,2000,21101,4,5,7,204,7,99"), &[]);
+    assert_eq!(outputs, vec![Ok(9)]);
+    assert_eq!(cpu.mmu.get(2007), 9);
+
+    // Bounded memory reports accesses past the limit instead of growing
+    let memory =
+        BoundedMemory::new(DenseMemory::from(parse_program::<i64>("1101,2,3,1000,99")), 64);
+    let mut cpu = CPU::with_memory(memory);
+    assert_eq!(
+        cpu.try_outputs_with(|| 0).collect::<Outputs<i64>>(),
+        vec![Err(CpuError::AddressOutOfBounds { pc: 0, word: 1101, address: 1000 })]
+    );
+    assert_eq!(cpu.compile().try_outputs_with(|| 0).collect::<Outputs<i64>>().len(), 1);
+}
+
+#[test]
+fn word_sizes() {
+    assert_eq!(run::<i16>("104,32767,99", &[]), vec![Ok(i16::MAX)]);
+    assert_eq!(run::<i16>("1101,-32768,0,0,4,0,99", &[]), vec![Ok(i16::MIN)]);
+    assert_eq!(
+        run::<i16>("1101,32767,1,0,4,0,99", &[]),
+        vec![Err(CpuError::Overflow { pc: 0, word: 1101 })]
+    );
+
+    assert_eq!(run::<i32>("1102,46340,46340,0,4,0,99", &[]), vec![Ok(2_147_395_600)]);
+    assert_eq!(
+        run::<i32>("1102,65536,32768,0,4,0,99", &[]),
+        vec![Err(CpuError::Overflow { pc: 0, word: 1102 })]
+    );
+
+    assert_eq!(run::<i64>("104,1125899906842624,99", &[]), vec![Ok(1_125_899_906_842_624)]);
+    assert_eq!(
+        run::<i64>("1102,34915192,34915192,7,4,7,99,0", &[]),
+        vec![Ok(1_219_070_632_396_864)]
+    );
+    assert_eq!(
+        run::<i64>("1101,9223372036854775807,1,0,99", &[]),
+        vec![Err(CpuError::Overflow { pc: 0, word: 1101 })]
+    );
+
+    // Arbitrary precision words never overflow
+    assert_eq!(
+        run::<BigInt>("1102,9223372036854775807,4,0,4,0,99", &[]),
+        vec![Ok("36893488147419103228".parse().unwrap())]
+    );
+
+    // Offsets past the range of addresses are reported rather than truncated
+    assert_eq!(
+        run::<BigInt>("109,36893488147419103228,99", &[]),
+        vec![Err(CpuError::Overflow { pc: 0, word: 109.into() })]
+    );
+    assert_eq!(
+        run::<BigInt>("204,36893488147419103228,99", &[]),
+        vec![Err(CpuError::Overflow { pc: 0, word: 204.into() })]
+    );
+
+    // The arithmetic mode decides what happens on overflow instead
+    for &(arithmetic, expected) in
+        &[(Arithmetic::Wrapping, Ok(i16::MIN)), (Arithmetic::Saturating, Ok(i16::MAX))]
+    {
+        let mut cpu = CPU::<i16>::from_source("1101,32767,1,0,4,0,99");
+        cpu.set_arithmetic(arithmetic);
+        assert_eq!(cpu.compile().try_outputs_with(|| 0).collect::<Outputs<i16>>(), vec![expected]);
+        assert_eq!(cpu.try_outputs_with(|| 0).collect::<Outputs<i16>>(), vec![expected]);
+    }
+}
+
+#[test]
+fn self_modifying_code() {
+    // Doubles the ADD at 4 into a MUL before running it
+    let (cpu, outputs) = execute(parse_program::<i64>("1,1,1,4,99,5,6,0,99"), &[]);
+    assert_eq!(outputs, vec![]);
+    assert_eq!(cpu.mmu.get(0), 30);
+
+    // Increments the immediate of its own WRT until it reaches 4
+    assert_eq!(
+        run::<i64>("104,1,1001,1,1,1,1008,1,4,14,1006,14,0,99,0", &[]),
+        vec![Ok(1), Ok(2), Ok(3)]
+    );
+
+    // Reads the next instruction from input and then runs it
+    assert_eq!(run::<i64>("3,2,0,7,99", &[104]), vec![Ok(7)]);
+}
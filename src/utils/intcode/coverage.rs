@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::disasm::disassemble;
+use super::memory::Memory;
+use super::{Instruction, Word, CPU};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCoverage {
+    pub taken: usize,
+    pub fallthrough: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    hits: BTreeMap<usize, usize>,
+    branches: BTreeMap<usize, BranchCoverage>,
+    // The direction of a branch is only known once the next instruction is fetched
+    pending: Option<(usize, usize)>,
+}
+
+impl Coverage {
+    pub(super) fn record<T>(&mut self, pc: usize, instruction: &Instruction<T>) {
+        if let Some((branch, fallthrough)) = self.pending.take() {
+            let branch = self.branches.entry(branch).or_default();
+            if pc == fallthrough {
+                branch.fallthrough += 1;
+            } else {
+                branch.taken += 1;
+            }
+        }
+
+        *self.hits.entry(pc).or_insert(0) += 1;
+        if let Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) = instruction {
+            self.pending = Some((pc, pc + 3));
+        }
+    }
+
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&address, &hits) in &other.hits {
+            *self.hits.entry(address).or_insert(0) += hits;
+        }
+        for (&address, branch) in &other.branches {
+            let merged = self.branches.entry(address).or_default();
+            merged.taken += branch.taken;
+            merged.fallthrough += branch.fallthrough;
+        }
+    }
+
+    pub fn hits(&self, address: usize) -> usize {
+        self.hits.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn branch(&self, address: usize) -> BranchCoverage {
+        self.branches.get(&address).copied().unwrap_or_default()
+    }
+}
+
+impl<T, M> CPU<T, M> {
+    pub fn enable_coverage(&mut self) {
+        if self.coverage.is_none() {
+            self.coverage = Some(Box::default());
+        }
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_deref()
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take().map(|coverage| *coverage)
+    }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    // Uncovered instructions are marked with ##### like gcov does. Executed addresses are used as
+    // extra entry points, so code only reached through computed jumps is still decoded.
+    pub fn coverage_listing(&self, coverage: &Coverage) -> String {
+        let entries: Vec<_> = Some(0).into_iter().chain(coverage.hits.keys().copied()).collect();
+        let disassembly = disassemble(&self.mmu, &entries);
+
+        let (mut instructions, mut covered) = (0, 0);
+        let (mut directions, mut covered_directions) = (0, 0);
+        let mut listing = String::new();
+
+        for line in &disassembly.lines {
+            let instruction = match &line.instruction {
+                Some(instruction) => instruction,
+                None => {
+                    writeln!(listing, "{:>8} | {}", "-", line).unwrap();
+                    continue;
+                }
+            };
+
+            let hits = coverage.hits(line.address);
+            instructions += 1;
+            if hits > 0 {
+                covered += 1;
+                write!(listing, "{:>8} | {}", hits, line).unwrap();
+            } else {
+                write!(listing, "{:>8} | {}", "#####", line).unwrap();
+            }
+
+            if let Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) = instruction {
+                let branch = coverage.branch(line.address);
+                directions += 2;
+                covered_directions +=
+                    (branch.taken > 0) as usize + (branch.fallthrough > 0) as usize;
+                write!(listing, " | taken {}, fallthrough {}", branch.taken, branch.fallthrough)
+                    .unwrap();
+            }
+            listing.push('\n');
+        }
+
+        writeln!(
+            listing,
+            "Covered {}/{} instructions and {}/{} branch directions",
+            covered, instructions, covered_directions, directions
+        )
+        .unwrap();
+        listing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Outputs 0 if the input was 0 and 1 otherwise, from the day 5 examples
+    const JUMPS: &str = "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9";
+
+    fn run(input: i64) -> Coverage {
+        let mut cpu = CPU::<i64>::from_source(JUMPS);
+        cpu.enable_coverage();
+        cpu.outputs_with(move || input).for_each(drop);
+        cpu.take_coverage().unwrap()
+    }
+
+    #[test]
+    fn records_addresses_and_branches() {
+        let coverage = run(0);
+        assert_eq!(
+            (coverage.hits(0), coverage.hits(2), coverage.hits(5), coverage.hits(9)),
+            (1, 1, 0, 1)
+        );
+        assert_eq!(coverage.branch(2), BranchCoverage { taken: 1, fallthrough: 0 });
+
+        let mut merged = coverage.clone();
+        merged.merge(&run(5));
+        merged.merge(&run(7));
+        assert_eq!((merged.hits(0), merged.hits(5)), (3, 2));
+        assert_eq!(merged.branch(2), BranchCoverage { taken: 1, fallthrough: 2 });
+    }
+
+    #[test]
+    fn annotated_listing() {
+        let cpu = CPU::<i64>::from_source(JUMPS);
+        let listing = cpu.coverage_listing(&run(0));
+        let lines: Vec<_> = listing.lines().collect();
+
+        assert!(lines[0].starts_with("       1 |     0: RED IN(12)"));
+        assert!(lines[1].ends_with("; 6,12,15 | taken 1, fallthrough 0"));
+        assert!(lines[2].starts_with("   ##### |     5: ADD IN(13), IN(14), IN(13)"));
+        assert!(lines[5].starts_with("       - |    12: DAT -1"));
+        assert_eq!(lines.last(), Some(&"Covered 4/5 instructions and 1/2 branch directions"));
+
+        let mut coverage = run(0);
+        coverage.merge(&run(1));
+        let listing = cpu.coverage_listing(&coverage);
+        assert!(listing.ends_with("Covered 5/5 instructions and 2/2 branch directions\n"));
+    }
+
+    #[test]
+    fn covers_every_noun_and_verb() {
+        let source = include_str!("../../day02/input");
+        let mut coverage = Coverage::default();
+        for noun in 0..10 {
+            for verb in 0..10 {
+                let mut cpu = CPU::<i64>::from_source(source);
+                *cpu.mmu.get_mut(1) = noun;
+                *cpu.mmu.get_mut(2) = verb;
+                cpu.enable_coverage();
+                cpu.outputs().for_each(drop);
+                coverage.merge(cpu.coverage().unwrap());
+            }
+        }
+        assert_eq!(coverage.hits(0), 100);
+        let listing = CPU::<i64>::from_source(source).coverage_listing(&coverage);
+        assert!(!listing.contains("#####"));
+    }
+}
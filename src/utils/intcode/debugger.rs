@@ -0,0 +1,500 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::str::FromStr;
+
+use super::disasm::Line;
+use super::history::HistoryEntry;
+use super::memory::{DenseMemory, Memory};
+use super::resume::Status;
+use super::{CpuError, Instruction, Word, CPU};
+
+// Most words or lines a single command prints
+const MAX_COUNT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop<T> {
+    Stepped,
+    Breakpoint(usize),
+    Output(T),
+    Input(usize),
+    Halted(usize),
+    Error(CpuError<T>),
+}
+
+impl<T: fmt::Display> fmt::Display for Stop<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stepped => write!(f, "Stepped"),
+            Self::Breakpoint(address) => write!(f, "Hit breakpoint at {}", address),
+            Self::Output(value) => write!(f, "Output {}", value),
+            Self::Input(address) => write!(f, "Waiting for input at {}", address),
+            Self::Halted(address) => write!(f, "Halted at {}", address),
+            Self::Error(err) => write!(f, "Error: {}", err),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Debugger<T, M = DenseMemory<T>> {
+    cpu: CPU<T, M>,
+    breakpoints: BTreeSet<usize>,
+    // Everything output so far, which rewinding truncates again
+    outputs: Vec<T>,
+}
+
+impl<T: Word, M: Memory<T>> Debugger<T, M> {
+    pub fn new(cpu: CPU<T, M>) -> Debugger<T, M> {
+        Debugger { cpu, breakpoints: BTreeSet::new(), outputs: Vec::new() }
+    }
+
+    pub fn cpu(&self) -> &CPU<T, M> {
+        &self.cpu
+    }
+
+    pub fn into_inner(self) -> CPU<T, M> {
+        self.cpu
+    }
+
+    pub fn pc(&self) -> usize {
+        self.cpu.pc
+    }
+
+    pub fn rbo(&self) -> isize {
+        self.cpu.rbo
+    }
+
+    pub fn memory(&self, range: Range<usize>) -> Vec<T> {
+        range.map(|address| self.cpu.mmu.get(address)).collect()
+    }
+
+    // Writes nothing and returns false if any of the values would land past the memory limit
+    pub fn patch(&mut self, address: usize, values: &[T]) -> bool {
+        let end = match address.checked_add(values.len()) {
+            Some(end) => end,
+            None => return false,
+        };
+        if self.cpu.mmu.limit().is_some_and(|limit| end > limit) {
+            return false;
+        }
+        for (offset, value) in values.iter().enumerate() {
+            *self.cpu.mmu.get_mut(address + offset) = value.clone();
+        }
+        true
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn push_input(&mut self, value: T) {
+        self.cpu.provide_input(value);
+    }
+
+    pub fn outputs(&self) -> &[T] {
+        &self.outputs
+    }
+
+    pub fn take_outputs(&mut self) -> Vec<T> {
+        std::mem::take(&mut self.outputs)
+    }
+
+    pub fn enable_history(&mut self, limit: Option<usize>) {
+        self.cpu.enable_history(limit);
+    }
+
+    // Outputs of undone instructions are forgotten, so the program can be resumed with
+    // different inputs
+    fn rewound(&mut self, undone: Option<Vec<HistoryEntry<T>>>) -> bool {
+        match undone {
+            Some(undone) => {
+                let outputs = undone.iter().filter(|entry| entry.output.is_some()).count();
+                self.outputs.truncate(self.outputs.len().saturating_sub(outputs));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn step_back(&mut self, count: usize) -> bool {
+        let undone = self.cpu.step_back(count);
+        self.rewound(undone)
+    }
+
+    pub fn rewind_to_write(&mut self, address: usize) -> bool {
+        let undone = self.cpu.rewind_to_last_write(address);
+        self.rewound(undone)
+    }
+
+    pub fn line(&self, address: usize) -> Line<T> {
+        // Decoding reads the parameters after the opcode, which mustn't wrap around
+        let instruction = address
+            .checked_add(self.cpu.mmu.extensions.longest_instruction())
+            .and_then(|_| Instruction::decode(&self.cpu.mmu, address).ok());
+        let size = instruction.as_ref().map_or(1, Instruction::len);
+        let words = (0..size)
+            .filter_map(|offset| address.checked_add(offset))
+            .map(|address| self.cpu.mmu.get(address))
+            .collect();
+        Line { address, words, instruction }
+    }
+
+    pub fn listing(&self, address: usize, count: usize) -> Vec<Line<T>> {
+        let mut lines = Vec::new();
+        let mut address = Some(address);
+        for _ in 0..count {
+            let line = match address {
+                Some(address) => self.line(address),
+                None => break,
+            };
+            address = line.address.checked_add(line.words.len());
+            lines.push(line);
+        }
+        lines
+    }
+
+    // Steps through the same fetch hooks as any other run, so budgets, the decode cache,
+    // profiling and coverage all see the instructions executed here
+    fn step_once(&mut self) -> Option<Stop<T>> {
+        let pc = self.cpu.pc;
+        match self.cpu.try_step() {
+            Ok(None) => None,
+            Ok(Some(Status::NeedsInput)) => Some(Stop::Input(pc)),
+            Ok(Some(Status::Output(value))) => {
+                self.outputs.push(value.clone());
+                Some(Stop::Output(value))
+            }
+            Ok(Some(Status::Halted)) => Some(Stop::Halted(pc)),
+            Err(err) => Some(Stop::Error(err)),
+        }
+    }
+
+    pub fn step(&mut self, count: usize) -> Stop<T> {
+        for _ in 0..count {
+            match self.step_once() {
+                None | Some(Stop::Output(_)) => {}
+                Some(stop) => return stop,
+            }
+        }
+        Stop::Stepped
+    }
+
+    fn run_until(&mut self, stop_on: impl Fn(&Stop<T>) -> bool) -> Stop<T> {
+        let mut first = true;
+        loop {
+            if !first && self.breakpoints.contains(&self.cpu.pc) {
+                return Stop::Breakpoint(self.cpu.pc);
+            }
+            first = false;
+
+            match self.step_once() {
+                Some(stop) if stop_on(&stop) => return stop,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> Stop<T> {
+        self.run_until(|stop| !matches!(stop, Stop::Output(_)))
+    }
+
+    pub fn run_until_output(&mut self) -> Stop<T> {
+        self.run_until(|_| true)
+    }
+
+    pub fn run_until_input(&mut self) -> Stop<T> {
+        let mut first = true;
+        loop {
+            if !first && self.breakpoints.contains(&self.cpu.pc) {
+                return Stop::Breakpoint(self.cpu.pc);
+            }
+            first = false;
+
+            if let Ok(Instruction::Read(_)) = self.cpu.try_fetch_instruction() {
+                return Stop::Input(self.cpu.pc);
+            }
+
+            match self.step_once() {
+                None | Some(Stop::Output(_)) => {}
+                Some(stop) => return stop,
+            }
+        }
+    }
+}
+
+fn parse_args<V: FromStr>(args: &[&str]) -> Option<Vec<V>> {
+    args.iter().map(|arg| arg.parse().ok()).collect()
+}
+
+impl<T: Word + FromStr, M: Memory<T>> Debugger<T, M> {
+    pub fn command(&mut self, line: &str) -> String {
+        let words: Vec<_> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return String::new(),
+        };
+
+        let stop = match (command, parse_args::<usize>(args).as_deref()) {
+            ("b", Some(&[address])) => {
+                self.add_breakpoint(address);
+                return format!("Breakpoint at {}", address);
+            }
+            ("d", Some(&[address])) => {
+                return if self.remove_breakpoint(address) {
+                    format!("Removed breakpoint at {}", address)
+                } else {
+                    format!("No breakpoint at {}", address)
+                };
+            }
+            ("s", Some(&[])) => self.step(1),
+            ("s", Some(&[count])) => self.step(count),
+            ("c", Some(&[])) => self.run(),
+            ("o", Some(&[])) => self.run_until_output(),
+            ("i", Some(&[])) => self.run_until_input(),
+            ("bs", Some(&[])) => return self.rewind(|debugger| debugger.step_back(1)),
+            ("bs", Some(&[count])) => return self.rewind(|debugger| debugger.step_back(count)),
+            ("bw", Some(&[address])) => {
+                return self.rewind(|debugger| debugger.rewind_to_write(address));
+            }
+            ("r", Some(&[])) => return format!("pc={} rbo={}", self.pc(), self.rbo()),
+            ("x", Some(&[address])) => return self.dump(address, 1),
+            ("x", Some(&[address, count])) => return self.dump(address, count),
+            ("l", Some(&[])) => return self.list(self.pc(), 10),
+            ("l", Some(&[address])) => return self.list(address, 10),
+            ("l", Some(&[address, count])) => return self.list(address, count),
+            ("in", _) => match parse_args::<T>(args) {
+                Some(values) if !values.is_empty() => {
+                    self.cpu.provide_inputs(values);
+                    return format!("{} input(s) queued", self.cpu.pending_inputs());
+                }
+                _ => return format!("Invalid command: {}", line.trim()),
+            },
+            ("w", _) => {
+                let address = args.first().and_then(|address| address.parse().ok());
+                match (address, parse_args::<T>(args.get(1..).unwrap_or(&[]))) {
+                    (Some(address), Some(values)) if !values.is_empty() => {
+                        return if self.patch(address, &values) {
+                            self.dump(address, values.len())
+                        } else {
+                            format!(
+                                "Writing {} value(s) at {} exceeds the memory limit",
+                                values.len(),
+                                address
+                            )
+                        };
+                    }
+                    _ => return format!("Invalid command: {}", line.trim()),
+                }
+            }
+            _ => return format!("Invalid command: {}", line.trim()),
+        };
+
+        format!("{}\n{}", stop, self.line(self.pc()))
+    }
+
+    fn rewind(&mut self, rewind: impl FnOnce(&mut Self) -> bool) -> String {
+        if !rewind(self) {
+            return "Can't rewind that far".to_string();
+        }
+        let step = self.cpu.history().map_or(0, |history| history.executed());
+        format!("Rewound to step {}\n{}", step, self.line(self.pc()))
+    }
+
+    fn dump(&self, address: usize, count: usize) -> String {
+        let end = match address.checked_add(count.min(MAX_COUNT)) {
+            Some(end) => end,
+            None => return format!("Address {} is out of range", address),
+        };
+        let values: Vec<_> = self.memory(address..end).iter().map(T::to_string).collect();
+        format!("{}: {}", address, values.join(","))
+    }
+
+    fn list(&self, address: usize, count: usize) -> String {
+        let lines: Vec<_> = self
+            .listing(address, count.min(MAX_COUNT))
+            .iter()
+            .map(|line| {
+                let marker = if line.address == self.pc() { '>' } else { ' ' };
+                format!("{}{}", marker, line)
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    pub fn prompt(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        write!(output, "(intcode) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if line.trim() == "q" {
+                break;
+            }
+            writeln!(output, "{}", self.command(&line))?;
+            write!(output, "(intcode) ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::budget::Budget;
+    use crate::utils::intcode::memory::BoundedMemory;
+
+    const COUNTDOWN: &str = "3,12,4,12,1001,12,-1,12,1005,12,2,99,0";
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let mut debugger = Debugger::new(CPU::<i64>::from_source(COUNTDOWN));
+        debugger.add_breakpoint(8);
+
+        assert_eq!(debugger.run(), Stop::Input(0));
+        debugger.push_input(3);
+        assert_eq!(debugger.run(), Stop::Breakpoint(8));
+        assert_eq!(debugger.outputs(), &[3]);
+        assert_eq!(debugger.memory(12..13), vec![2]);
+
+        assert_eq!(debugger.step(1), Stop::Stepped);
+        assert_eq!(debugger.pc(), 2);
+        assert_eq!(debugger.run_until_output(), Stop::Output(2));
+
+        assert!(debugger.patch(12, &[1]));
+        assert!(debugger.remove_breakpoint(8));
+        assert_eq!(debugger.run(), Stop::Halted(11));
+        assert_eq!(debugger.take_outputs(), vec![3, 2]);
+    }
+
+    #[test]
+    fn runs_until_input() {
+        let mut debugger = Debugger::new(CPU::<i64>::from_source("104,1,3,0,99"));
+        assert_eq!(debugger.run_until_input(), Stop::Input(2));
+        assert_eq!(debugger.outputs(), &[1]);
+        debugger.push_input(7);
+        assert_eq!(debugger.step(5), Stop::Halted(4));
+        assert_eq!(debugger.memory(0..1), vec![7]);
+    }
+
+    #[test]
+    fn steps_backwards() {
+        let mut debugger = Debugger::new(CPU::<i64>::from_source(COUNTDOWN));
+        debugger.enable_history(None);
+        debugger.push_input(2);
+        assert_eq!(debugger.run(), Stop::Halted(11));
+        assert_eq!(debugger.outputs(), &[2, 1]);
+
+        assert!(!debugger.rewind_to_write(0));
+        assert_eq!(
+            debugger.command("bw 12"),
+            "Rewound to step 5\n    4: ADD IN(12), IM(-1), IN(12)       ; 1001,12,-1,12"
+        );
+        assert_eq!(debugger.outputs(), &[2, 1]);
+        assert_eq!(
+            debugger.command("bs 2"),
+            "Rewound to step 3\n    8: JIT IN(12), IM(2)                ; 1005,12,2"
+        );
+        assert_eq!(debugger.outputs(), &[2]);
+        assert_eq!(debugger.command("bs 9"), "Can't rewind that far");
+
+        // Going back to before the read lets the countdown start from another value
+        while debugger.rewind_to_write(12) {}
+        assert_eq!((debugger.pc(), debugger.outputs()), (0, &[][..]));
+        debugger.push_input(3);
+        assert_eq!(debugger.run(), Stop::Halted(11));
+        assert_eq!(debugger.outputs(), &[3, 2, 1]);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut debugger = Debugger::new(CPU::<i64>::from_source("1101,1,1,0,42"));
+        assert_eq!(debugger.run(), Stop::Error(CpuError::InvalidOpcode { pc: 4, word: 42 }));
+    }
+
+    #[test]
+    fn steps_through_cpu_hooks() {
+        let mut cpu = CPU::<i64>::from_source(COUNTDOWN);
+        cpu.set_budget(Budget { instructions: Some(6), ..Budget::default() });
+        cpu.enable_profiling();
+        cpu.enable_decode_cache();
+        let mut debugger = Debugger::new(cpu);
+
+        assert_eq!(debugger.step(1), Stop::Input(0));
+        debugger.push_input(5);
+        assert_eq!(debugger.run(), Stop::Error(CpuError::BudgetExhausted { pc: 8, word: 1005 }));
+        assert_eq!(debugger.outputs(), &[5, 4]);
+
+        let cpu = debugger.into_inner();
+        assert_eq!(cpu.executed_instructions(), Some(6));
+        assert_eq!(cpu.profile().unwrap().hits(0), 1);
+        assert!(cpu.decode_cache_stats().unwrap().hits > 0);
+    }
+
+    #[test]
+    fn bad_commands_dont_panic() {
+        let cpu = CPU::with_memory(BoundedMemory::new(DenseMemory::from(vec![104i64, 1, 99]), 8));
+        let mut debugger = Debugger::new(cpu);
+        assert_eq!(
+            debugger.command("w 100 5"),
+            "Writing 1 value(s) at 100 exceeds the memory limit"
+        );
+        assert_eq!(
+            debugger.command("w 6 5 5 5"),
+            "Writing 3 value(s) at 6 exceeds the memory limit"
+        );
+        assert_eq!(debugger.command("w 6 5 5"), "6: 5,5");
+        assert_eq!(debugger.command("x 5 3"), "5: 0,5,5");
+
+        let max = usize::MAX;
+        assert_eq!(debugger.command(&format!("x 5 {}", max)).matches(',').count(), MAX_COUNT - 1);
+        assert_eq!(
+            debugger.command(&format!("x {} 2", max)),
+            format!("Address {} is out of range", max)
+        );
+        assert_eq!(
+            debugger.command(&format!("w {} 1 2", max)),
+            format!("Writing 2 value(s) at {} exceeds the memory limit", max)
+        );
+        assert_eq!(debugger.command(&format!("l 0 {}", max)).lines().count(), MAX_COUNT);
+        assert_eq!(debugger.command(&format!("l {} 3", max - 1)).lines().count(), 2);
+    }
+
+    #[test]
+    fn command_prompt() {
+        let mut debugger = Debugger::new(CPU::<i64>::from_source(COUNTDOWN));
+        let input = "b 8\nin 2\nc\nr\nx 12\nw 12 0\nl 8 2\nd 8\nc\nfoo\nq\nr\n";
+        let mut output = Vec::new();
+        debugger.prompt(input.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "(intcode) Breakpoint at 8",
+                "(intcode) 1 input(s) queued",
+                "(intcode) Hit breakpoint at 8",
+                "    8: JIT IN(12), IM(2)                ; 1005,12,2",
+                "(intcode) pc=8 rbo=0",
+                "(intcode) 12: 1",
+                "(intcode) 12: 0",
+                "(intcode) >    8: JIT IN(12), IM(2)                ; 1005,12,2",
+                "    11: BRK                              ; 99",
+                "(intcode) Removed breakpoint at 8",
+                "(intcode) Halted at 11",
+                "   11: BRK                              ; 99",
+                "(intcode) Invalid command: foo",
+                "(intcode)",
+            ]
+        );
+    }
+}
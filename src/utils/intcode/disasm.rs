@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+
+use super::memory::Memory;
+use super::{Instruction, Parameter, Word, CPU, MMU};
+
+#[derive(Debug, Clone)]
+pub struct Line<T> {
+    pub address: usize,
+    pub words: Vec<T>,
+    pub instruction: Option<Instruction<T>>,
+}
+
+impl<T: Word> fmt::Display for Line<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words: Vec<_> = self.words.iter().map(T::to_string).collect();
+        let code = match &self.instruction {
+            Some(instruction) if instruction.encode() == self.words[0] => instruction.to_string(),
+            // Words with stray mode digits are kept as data, so the listing still reassembles
+            // to the same program
+            _ => format!("DAT {}", words.join(", ")),
+        };
+        write!(f, "{:>5}: {:<32} ; {}", self.address, code, words.join(","))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Disassembly<T> {
+    pub lines: Vec<Line<T>>,
+}
+
+impl<T> Disassembly<T> {
+    pub fn line_at(&self, address: usize) -> Option<&Line<T>> {
+        let index = self.lines.binary_search_by_key(&address, |line| line.address).ok()?;
+        self.lines.get(index)
+    }
+}
+
+impl<T: Word> fmt::Display for Disassembly<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn immediate<T: Word>(param: &Parameter<T>) -> Option<&T> {
+    match param {
+        Parameter::Immediate(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn successors<T: Word>(address: usize, instruction: &Instruction<T>) -> Vec<usize> {
+    let next = address + instruction.len();
+    let target = |param| immediate(param).and_then(|value: &T| value.to_usize());
+
+    match instruction {
+        Instruction::Break => vec![],
+        Instruction::JumpIfTrue(cond, param) => match immediate(cond) {
+            Some(value) if !value.is_zero() => target(param).into_iter().collect(),
+            Some(_) => vec![next],
+            None => target(param).into_iter().chain(Some(next)).collect(),
+        },
+        Instruction::JumpIfFalse(cond, param) => match immediate(cond) {
+            Some(value) if value.is_zero() => target(param).into_iter().collect(),
+            Some(_) => vec![next],
+            None => target(param).into_iter().chain(Some(next)).collect(),
+        },
+        _ => vec![next],
+    }
+}
+
+// Return addresses are usually pushed with `ADD IM(addr), IM(0), ..` or `MUL IM(addr), IM(1), ..`
+// before jumping away, so moved immediates are treated as possible code pointers.
+pub(super) fn code_pointer<T: Word>(instruction: &Instruction<T>) -> Option<usize> {
+    let moved = match instruction {
+        Instruction::Add(a, b, _) => match (immediate(a), immediate(b)) {
+            (Some(value), Some(other)) | (Some(other), Some(value)) if other.is_zero() => value,
+            _ => return None,
+        },
+        Instruction::Multiply(a, b, _) => match (immediate(a), immediate(b)) {
+            (Some(value), Some(other)) | (Some(other), Some(value)) if other.is_one() => value,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    moved.to_usize()
+}
+
+// Only the covered addresses are tracked, so this stays cheap for sparse memory
+fn reachable<T, M>(mmu: &MMU<T, M>, entries: &[usize]) -> BTreeMap<usize, Instruction<T>>
+where
+    T: Word,
+    M: Memory<T>,
+{
+    let len = mmu.len();
+    let mut owned = HashSet::new();
+    let mut code = BTreeMap::new();
+
+    let mut queue: VecDeque<_> = entries.iter().cloned().collect();
+    let mut pointers = VecDeque::new();
+
+    while let Some(address) = queue.pop_front().or_else(|| pointers.pop_front()) {
+        if address >= len || owned.contains(&address) {
+            continue;
+        }
+
+        let instruction = match Instruction::decode(mmu, address) {
+            Ok(instruction) => instruction,
+            Err(_) => continue,
+        };
+
+        let end = address + instruction.len();
+        if end > len || (address..end).any(|covered| owned.contains(&covered)) {
+            continue;
+        }
+        owned.extend(address..end);
+
+        queue.extend(successors(address, &instruction));
+        pointers.extend(code_pointer(&instruction));
+        code.insert(address, instruction);
+    }
+    code
+}
+
+pub fn disassemble<T, M>(mmu: &MMU<T, M>, entries: &[usize]) -> Disassembly<T>
+where
+    T: Word,
+    M: Memory<T>,
+{
+    let len = mmu.len();
+    let code = reachable(mmu, entries);
+
+    let mut lines = Vec::new();
+    let mut address = 0;
+
+    while address < len {
+        let instruction = code.get(&address).cloned();
+        let size = instruction.as_ref().map_or(1, Instruction::len);
+        let words = (address..address + size).map(|i| mmu.get(i)).collect();
+        lines.push(Line { address, words, instruction });
+        address += size;
+    }
+
+    Disassembly { lines }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    pub fn disassemble(&self) -> Disassembly<T> {
+        disassemble(&self.mmu, &[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_after_break_is_not_decoded() {
+        let cpu = CPU::<i64>::from_source("1105,1,5,1,1,104,42,99,1101,0,0,0");
+        let listing = cpu.disassemble().to_string();
+
+        assert_eq!(
+            listing.lines().map(str::trim_end).collect::<Vec<_>>(),
+            vec![
+                "    0: JIT IM(1), IM(5)                 ; 1105,1,5",
+                "    3: DAT 1                            ; 1",
+                "    4: DAT 1                            ; 1",
+                "    5: WRT IM(42)                       ; 104,42",
+                "    7: BRK                              ; 99",
+                "    8: DAT 1101                         ; 1101",
+                "    9: DAT 0                            ; 0",
+                "   10: DAT 0                            ; 0",
+                "   11: DAT 0                            ; 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn return_addresses_are_followed() {
+        // Pushes a return address, calls a subroutine at 8 and returns through RE(0)
+        let cpu = CPU::<i64>::from_source("21101,7,0,0,1105,1,8,99,104,1,2106,0,0");
+        let disassembly = cpu.disassemble();
+
+        assert!(disassembly.line_at(7).unwrap().instruction.is_some());
+        assert!(disassembly.line_at(8).unwrap().instruction.is_some());
+        assert_eq!(disassembly.lines.len(), 5);
+        assert_eq!(cpu.clone().outputs().collect::<Vec<_>>(), vec![1]);
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{Word, CPU};
+
+const BUILTIN_OPCODES: [usize; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99];
+
+// Words taken by ADD, MUL, LST and EQL
+const LONGEST_BUILTIN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect<T> {
+    Next,
+    Store(T),
+    Jump(T),
+    Output(T),
+    Exit(T),
+}
+
+pub trait Extension<T>: Send + Sync {
+    fn mnemonic(&self) -> &'static str;
+
+    fn arity(&self) -> usize;
+
+    // When set, the last parameter is the destination of `Effect::Store` and is not read
+    fn stores(&self) -> bool {
+        false
+    }
+
+    // Returning None reports an `ExtensionFailed` error, e.g. for a division by zero
+    fn execute(&self, args: &[T]) -> Option<Effect<T>>;
+}
+
+pub struct Extensions<T> {
+    entries: HashMap<usize, Arc<dyn Extension<T>>>,
+    // Words taken by the longest instruction, which bounds how far back a write can reach into
+    // an already decoded one
+    longest: usize,
+}
+
+impl<T> Extensions<T> {
+    pub fn new() -> Extensions<T> {
+        Extensions { entries: HashMap::new(), longest: LONGEST_BUILTIN }
+    }
+
+    pub fn get(&self, opcode: usize) -> Option<&Arc<dyn Extension<T>>> {
+        self.entries.get(&opcode)
+    }
+
+    pub fn find(&self, mnemonic: &str) -> Option<(usize, &Arc<dyn Extension<T>>)> {
+        self.entries
+            .iter()
+            .find(|(_, extension)| extension.mnemonic() == mnemonic)
+            .map(|(&opcode, extension)| (opcode, extension))
+    }
+
+    pub fn longest_instruction(&self) -> usize {
+        self.longest
+    }
+
+    fn insert(&mut self, opcode: usize, extension: Arc<dyn Extension<T>>) {
+        self.longest = self.longest.max(extension.arity() + 1);
+        self.entries.insert(opcode, extension);
+    }
+}
+
+impl<T> Default for Extensions<T> {
+    fn default() -> Extensions<T> {
+        Extensions::new()
+    }
+}
+
+impl<T> Clone for Extensions<T> {
+    fn clone(&self) -> Extensions<T> {
+        Extensions { entries: self.entries.clone(), longest: self.longest }
+    }
+}
+
+impl<T> fmt::Debug for Extensions<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut opcodes: Vec<_> = self.entries.iter().collect();
+        opcodes.sort_by_key(|(&opcode, _)| opcode);
+        f.debug_map()
+            .entries(opcodes.into_iter().map(|(opcode, extension)| (opcode, extension.mnemonic())))
+            .finish()
+    }
+}
+
+impl<T: Word, M> CPU<T, M> {
+    pub fn register_extension(&mut self, opcode: usize, extension: impl Extension<T> + 'static) {
+        assert!(
+            opcode < 100 && !BUILTIN_OPCODES.contains(&opcode),
+            "Opcode {} can't be extended",
+            opcode
+        );
+        self.mmu.extensions.insert(opcode, Arc::new(extension));
+        if let Some(cache) = &mut self.mmu.cache {
+            cache.clear();
+        }
+    }
+
+    pub fn exit_code(&self) -> Option<&T> {
+        self.exit_code.as_ref()
+    }
+
+    pub fn extensions(&self) -> &Extensions<T> {
+        &self.mmu.extensions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::asm::{assemble, assemble_with, AsmError};
+    use crate::utils::intcode::CpuError;
+
+    struct Bitwise(&'static str, fn(i64, i64) -> i64);
+
+    impl Extension<i64> for Bitwise {
+        fn mnemonic(&self) -> &'static str {
+            self.0
+        }
+
+        fn arity(&self) -> usize {
+            3
+        }
+
+        fn stores(&self) -> bool {
+            true
+        }
+
+        fn execute(&self, args: &[i64]) -> Option<Effect<i64>> {
+            Some(Effect::Store((self.1)(args[0], args[1])))
+        }
+    }
+
+    struct Modulo;
+
+    impl Extension<i64> for Modulo {
+        fn mnemonic(&self) -> &'static str {
+            "MOD"
+        }
+
+        fn arity(&self) -> usize {
+            3
+        }
+
+        fn stores(&self) -> bool {
+            true
+        }
+
+        fn execute(&self, args: &[i64]) -> Option<Effect<i64>> {
+            args[0].checked_rem(args[1]).map(Effect::Store)
+        }
+    }
+
+    struct Exit;
+
+    impl Extension<i64> for Exit {
+        fn mnemonic(&self) -> &'static str {
+            "EXT"
+        }
+
+        fn arity(&self) -> usize {
+            1
+        }
+
+        fn execute(&self, args: &[i64]) -> Option<Effect<i64>> {
+            Some(Effect::Exit(args[0]))
+        }
+    }
+
+    // Outputs the sum of its five arguments
+    struct Sum;
+
+    impl Extension<i64> for Sum {
+        fn mnemonic(&self) -> &'static str {
+            "SUM"
+        }
+
+        fn arity(&self) -> usize {
+            5
+        }
+
+        fn execute(&self, args: &[i64]) -> Option<Effect<i64>> {
+            Some(Effect::Output(args.iter().sum()))
+        }
+    }
+
+    fn teaching_cpu(source: &str) -> CPU<i64> {
+        let mut cpu = CPU::from_source(source);
+        cpu.register_extension(10, Bitwise("AND", |a, b| a & b));
+        cpu.register_extension(11, Bitwise("ORR", |a, b| a | b));
+        cpu.register_extension(12, Modulo);
+        cpu.register_extension(13, Exit);
+        cpu
+    }
+
+    #[test]
+    fn extensions_execute() {
+        let mut cpu = teaching_cpu("1110,12,10,0,1111,12,3,1,4,0,4,1,1112,17,5,2,4,2,113,7");
+        let mut compiled = cpu.compile();
+        assert_eq!(compiled.outputs().collect::<Vec<_>>(), vec![8, 15, 2]);
+        assert_eq!(compiled.exit_code(), Some(&7));
+        assert_eq!(cpu.outputs().collect::<Vec<_>>(), vec![8, 15, 2]);
+        assert_eq!(cpu.exit_code(), Some(&7));
+
+        let mut cpu = teaching_cpu("1112,1,0,0,99");
+        assert_eq!(
+            cpu.try_outputs_with(|| 0).collect::<Vec<_>>(),
+            vec![Err(CpuError::ExtensionFailed { pc: 0, word: 1112 })]
+        );
+
+        let mut cpu = CPU::<i64>::from_source("113,7");
+        assert_eq!(
+            cpu.try_outputs_with(|| 0).collect::<Vec<_>>(),
+            vec![Err(CpuError::InvalidOpcode { pc: 0, word: 113 })]
+        );
+    }
+
+    #[test]
+    fn writes_to_long_instructions_invalidate_them() {
+        // Adds 10 to the last argument of its own SUM, then jumps back to run it again
+        let mut cpu = CPU::<i64>::from_source("1111114,1,2,3,4,5,1001,5,10,5,1105,1,0");
+        cpu.register_extension(14, Sum);
+        assert_eq!(cpu.compile().outputs().take(3).collect::<Vec<_>>(), vec![15, 25, 35]);
+
+        cpu.enable_decode_cache();
+        assert_eq!(cpu.outputs().take(3).collect::<Vec<_>>(), vec![15, 25, 35]);
+        assert_eq!(cpu.decode_cache_stats().unwrap().invalidations, 2);
+    }
+
+    #[test]
+    fn extension_effects_are_traced() {
+        let mut cpu = teaching_cpu("1111114,1,2,3,4,5,1111,12,3,12,113,7,0");
+        cpu.register_extension(14, Sum);
+        cpu.start_trace();
+        assert_eq!(cpu.outputs().collect::<Vec<_>>(), vec![15]);
+
+        let trace: Vec<_> = cpu.stop_trace().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            trace,
+            vec![
+                "    0: SUM IM(1), IM(2), IM(3), IM(4), IM(5) ; read=1,2,3,4,5 out=15",
+                "    6: ORR IM(12), IM(3), IN(12) ; read=12,3 mem[12]=15",
+                "   10: EXT IM(7) ; read=7",
+            ]
+        );
+    }
+
+    #[test]
+    fn extensions_are_disassembled() {
+        let cpu = teaching_cpu("1110,12,10,0,1012,0,3,1,204,-1,113,0");
+        assert_eq!(
+            cpu.disassemble().to_string(),
+            "    0: AND IM(12), IM(10), IN(0)        ; 1110,12,10,0\n\
+             \x20   4: MOD IN(0), IM(3), IN(1)          ; 1012,0,3,1\n\
+             \x20   8: WRT RE(-1)                       ; 204,-1\n\
+             \x20  10: EXT IM(0)                        ; 113,0\n"
+        );
+
+        let listing = cpu.disassemble().to_string();
+        assert_eq!(
+            assemble_with::<i64>(&listing, cpu.extensions()),
+            Ok(vec![1110, 12, 10, 0, 1012, 0, 3, 1, 204, -1, 113, 0])
+        );
+        assert_eq!(
+            assemble::<i64>(&listing),
+            Err(AsmError::UnknownMnemonic { line: 1, mnemonic: "AND".to_string() })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Opcode 7 can't be extended")]
+    fn builtin_opcodes_are_reserved() {
+        CPU::<i64>::from_source("99").register_extension(7, Exit);
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+use super::disasm::{code_pointer, disassemble, immediate};
+use super::memory::Memory;
+use super::{Instruction, Parameter, Word, CPU};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    Fallthrough(usize),
+    Branch { target: usize, fallthrough: usize },
+    Jump(usize),
+    // An unconditional jump after pushing the address of the next instruction with a
+    // relative-mode write
+    Call { target: usize, return_to: usize },
+    // An unconditional jump through a relative-mode parameter
+    Return,
+    ComputedJump { fallthrough: Option<usize> },
+    Halt,
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock<T> {
+    pub start: usize,
+    pub end: usize,
+    pub instructions: Vec<(usize, Instruction<T>)>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone)]
+pub struct FlowGraph<T> {
+    pub blocks: BTreeMap<usize, BasicBlock<T>>,
+    // Immediates that get moved around and look like code addresses, e.g. return addresses
+    pub code_pointers: BTreeSet<usize>,
+    reachable: BTreeSet<usize>,
+}
+
+fn is_jump<T>(instruction: &Instruction<T>) -> bool {
+    matches!(
+        instruction,
+        Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) | Instruction::Break
+    )
+}
+
+fn pushes_return_address<T: Word>(instruction: &Instruction<T>, return_to: usize) -> bool {
+    match instruction {
+        Instruction::Add(_, _, Parameter::Relative(_))
+        | Instruction::Multiply(_, _, Parameter::Relative(_)) => {
+            code_pointer(instruction) == Some(return_to)
+        }
+        _ => false,
+    }
+}
+
+fn terminator<T: Word>(instructions: &[(usize, Instruction<T>)]) -> Terminator {
+    let (address, last) = instructions.last().unwrap();
+    let next = address + last.len();
+
+    let (cond, target, when_zero) = match last {
+        Instruction::JumpIfTrue(cond, target) => (cond, target, false),
+        Instruction::JumpIfFalse(cond, target) => (cond, target, true),
+        Instruction::Break => return Terminator::Halt,
+        _ => return Terminator::Fallthrough(next),
+    };
+
+    let unconditional = match immediate(cond) {
+        Some(value) if value.is_zero() != when_zero => return Terminator::Fallthrough(next),
+        Some(_) => true,
+        None => false,
+    };
+
+    match (target, unconditional) {
+        (Parameter::Immediate(value), _) => match value.to_usize() {
+            Some(target) if !unconditional => Terminator::Branch { target, fallthrough: next },
+            Some(target) => {
+                if instructions
+                    .iter()
+                    .any(|(_, instruction)| pushes_return_address(instruction, next))
+                {
+                    Terminator::Call { target, return_to: next }
+                } else {
+                    Terminator::Jump(target)
+                }
+            }
+            None => Terminator::Halt,
+        },
+        (Parameter::Relative(_), true) => Terminator::Return,
+        (_, true) => Terminator::ComputedJump { fallthrough: None },
+        (_, false) => Terminator::ComputedJump { fallthrough: Some(next) },
+    }
+}
+
+impl<T> FlowGraph<T> {
+    pub fn successors(&self, block: &BasicBlock<T>) -> Vec<usize> {
+        let successors = match block.terminator {
+            Terminator::Fallthrough(next) | Terminator::Jump(next) => vec![next],
+            Terminator::Branch { target, fallthrough } => vec![target, fallthrough],
+            Terminator::Call { target, return_to } => vec![target, return_to],
+            Terminator::Return | Terminator::Halt => vec![],
+            // Jumps through memory may land on any address the program moves around
+            Terminator::ComputedJump { fallthrough } => {
+                fallthrough.into_iter().chain(self.code_pointers.iter().copied()).collect()
+            }
+        };
+        successors.into_iter().filter(|start| self.blocks.contains_key(start)).collect()
+    }
+
+    pub fn is_reachable(&self, start: usize) -> bool {
+        self.reachable.contains(&start)
+    }
+
+    pub fn unreachable_blocks(&self) -> impl Iterator<Item = &BasicBlock<T>> {
+        self.blocks.values().filter(move |block| !self.is_reachable(block.start))
+    }
+}
+
+impl<T: Word> FlowGraph<T> {
+    pub fn to_dot(&self) -> String {
+        let mut dot =
+            String::from("digraph intcode {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, instruction) in &block.instructions {
+                write!(label, "{}: {}\\l", address, instruction).unwrap();
+            }
+            let mut attributes = format!("label=\"{}\"", label);
+            if !self.is_reachable(block.start) {
+                attributes.push_str(", style=filled, fillcolor=lightgrey");
+            }
+            if block.terminator == Terminator::Return {
+                attributes.push_str(", peripheries=2");
+            }
+            writeln!(dot, "    block{} [{}];", block.start, attributes).unwrap();
+        }
+
+        for block in self.blocks.values() {
+            let edges: Vec<(usize, &str)> = match block.terminator {
+                Terminator::Branch { target, fallthrough } => {
+                    vec![(target, "label=\"taken\""), (fallthrough, "label=\"fallthrough\"")]
+                }
+                Terminator::Call { target, return_to } => {
+                    vec![(target, "label=\"call\""), (return_to, "label=\"return\", style=dashed")]
+                }
+                Terminator::ComputedJump { fallthrough } => fallthrough
+                    .map(|next| (next, ""))
+                    .into_iter()
+                    .chain(self.code_pointers.iter().map(|&target| (target, "style=dotted")))
+                    .collect(),
+                _ => self.successors(block).into_iter().map(|next| (next, "")).collect(),
+            };
+            for (next, attributes) in edges {
+                if !self.blocks.contains_key(&next) {
+                    continue;
+                }
+                if attributes.is_empty() {
+                    writeln!(dot, "    block{} -> block{};", block.start, next).unwrap();
+                } else {
+                    writeln!(dot, "    block{} -> block{} [{}];", block.start, next, attributes)
+                        .unwrap();
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    pub fn flow_graph(&self) -> FlowGraph<T> {
+        let code: Vec<_> = disassemble(&self.mmu, &[0])
+            .lines
+            .into_iter()
+            .filter_map(|line| Some((line.address, line.instruction?)))
+            .collect();
+
+        let code_pointers: BTreeSet<_> =
+            code.iter().filter_map(|(_, instruction)| code_pointer(instruction)).collect();
+
+        let mut leaders: BTreeSet<_> = code_pointers.iter().copied().collect();
+        leaders.insert(0);
+        for (address, instruction) in &code {
+            if is_jump(instruction) {
+                leaders.insert(address + instruction.len());
+                if let Instruction::JumpIfTrue(_, target) | Instruction::JumpIfFalse(_, target) =
+                    instruction
+                {
+                    leaders.extend(immediate(target).and_then(|target| target.to_usize()));
+                }
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut current: Vec<(usize, Instruction<T>)> = Vec::new();
+        let mut close = |current: &mut Vec<(usize, Instruction<T>)>| {
+            if let Some((address, instruction)) = current.last() {
+                let start = current[0].0;
+                let end = address + instruction.len();
+                let terminator = terminator(current);
+                let instructions = std::mem::take(current);
+                blocks.insert(start, BasicBlock { start, end, instructions, terminator });
+            }
+        };
+
+        for (address, instruction) in code {
+            let contiguous =
+                current.last().is_some_and(|(previous, last)| previous + last.len() == address);
+            if !contiguous || leaders.contains(&address) {
+                close(&mut current);
+            }
+            let ends = is_jump(&instruction);
+            current.push((address, instruction));
+            if ends {
+                close(&mut current);
+            }
+        }
+        close(&mut current);
+
+        let mut graph = FlowGraph { blocks, code_pointers, reachable: BTreeSet::new() };
+        let mut queue: VecDeque<_> =
+            graph.blocks.get(&0).map(|block| block.start).into_iter().collect();
+        while let Some(start) = queue.pop_front() {
+            if graph.reachable.insert(start) {
+                queue.extend(graph.successors(&graph.blocks[&start]));
+            }
+        }
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::asm::assemble;
+
+    // Doubles a value in a subroutine, with a block that is only referenced through a pointer
+    const PROGRAM: &str = "
+                RBO IM(stack)
+                ADD IM(back), IM(0), RE(0)
+                JIT IM(1), IM(double)
+        back:   JIF IN(value), IM(skip)
+                WRT IN(value)
+        skip:   BRK
+        double: RBO IM(1)
+                MUL IN(value), IM(2), IN(value)
+                ADD IM(dead), IM(0), IN(scratch)
+                RBO IM(-1)
+                JIT IM(1), RE(0)
+        dead:   WRT IM(9)
+                BRK
+        value:  DAT 21
+        scratch: DAT 0
+        stack:  DAT 0
+    ";
+
+    #[test]
+    fn builds_blocks_with_calls_and_returns() {
+        let cpu = CPU::<i64>::new(assemble(PROGRAM).unwrap());
+        assert_eq!(cpu.clone().outputs().collect::<Vec<_>>(), vec![42]);
+
+        let graph = cpu.flow_graph();
+        let terminators: Vec<_> =
+            graph.blocks.values().map(|block| (block.start, block.terminator)).collect();
+        assert_eq!(
+            terminators,
+            vec![
+                (0, Terminator::Call { target: 15, return_to: 9 }),
+                (9, Terminator::Branch { target: 14, fallthrough: 12 }),
+                (12, Terminator::Fallthrough(14)),
+                (14, Terminator::Halt),
+                (15, Terminator::Return),
+                (30, Terminator::Halt),
+            ]
+        );
+
+        let unreachable: Vec<_> = graph.unreachable_blocks().map(|block| block.start).collect();
+        assert_eq!(unreachable, vec![30]);
+    }
+
+    #[test]
+    fn analyses_puzzle_programs() {
+        for &source in &[include_str!("../../day09/input"), include_str!("../../day13/input")] {
+            let graph = CPU::<i64>::from_source(source).flow_graph();
+            assert!(graph.is_reachable(0));
+            assert!(graph.blocks.values().any(|block| block.terminator == Terminator::Return));
+            assert!(graph.to_dot().ends_with("}\n"));
+        }
+    }
+
+    #[test]
+    fn exports_dot() {
+        let cpu = CPU::<i64>::from_source("3,9,1005,9,7,104,0,104,1,0");
+        let dot = cpu.flow_graph().to_dot();
+        assert_eq!(
+            dot,
+            "digraph intcode {\n    \
+                 node [shape=box, fontname=\"monospace\"];\n    \
+                 block0 [label=\"0: RED IN(9)\\l2: JIT IN(9), IM(7)\\l\"];\n    \
+                 block5 [label=\"5: WRT IM(0)\\l\"];\n    \
+                 block7 [label=\"7: WRT IM(1)\\l\"];\n    \
+                 block0 -> block7 [label=\"taken\"];\n    \
+                 block0 -> block5 [label=\"fallthrough\"];\n    \
+                 block5 -> block7;\n\
+             }\n"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+
+use super::budget::Budget;
+use super::memory::{BoundedMemory, DenseMemory, Memory, SparseMemory};
+use super::{CpuError, Word, CPU};
+
+// xorshift64*, which is plenty for generating programs and keeps runs reproducible from a seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    pub fn between(&mut self, low: i64, high: i64) -> i64 {
+        low + (self.next_u64() % (high - low + 1) as u64) as i64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FuzzConfig {
+    // Words of code generated, with the rest of memory left zeroed
+    pub code: usize,
+    pub memory: usize,
+    // Immediates are drawn from -values..=values
+    pub values: i64,
+    pub inputs: usize,
+    pub steps: usize,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> FuzzConfig {
+        FuzzConfig { code: 40, memory: 64, values: 20, inputs: 8, steps: 1000 }
+    }
+}
+
+// Only valid opcodes and modes are generated, but addresses, jump targets and relative offsets
+// are random, so programs still run into every kind of runtime error
+pub fn generate<T: Word>(rng: &mut Rng, config: &FuzzConfig) -> Vec<T> {
+    let mut program = Vec::with_capacity(config.memory);
+    while program.len() < config.code {
+        let opcode: i64 = match rng.below(20) {
+            0 => 99,
+            n => n as i64 % 9 + 1,
+        };
+        // Whether each parameter is written to
+        let params: &[bool] = match opcode {
+            1 | 2 | 7 | 8 => &[false, false, true],
+            3 => &[true],
+            4 | 9 => &[false],
+            5 | 6 => &[false, false],
+            _ => &[],
+        };
+
+        let mut word = opcode;
+        let mut words = Vec::with_capacity(params.len());
+        for (index, &writes) in params.iter().enumerate() {
+            let mode = if writes { 2 * rng.below(2) } else { rng.below(3) };
+            word += mode as i64 * 10i64.pow(index as u32 + 2);
+            words.push(match mode {
+                0 => rng.below(config.memory) as i64,
+                // Jump targets mostly stay inside the code
+                1 if (opcode == 5 || opcode == 6) && index == 1 => rng.below(config.code) as i64,
+                // Keep the relative base from drifting below zero straight away
+                1 if opcode == 9 => rng.between(-2, 8),
+                1 => rng.between(-config.values, config.values),
+                _ => rng.between(0, 8),
+            });
+        }
+        program.push(word);
+        program.extend(words);
+    }
+    program.resize(config.memory, 0);
+    program.into_iter().map(|word| T::from_i64(word).unwrap()).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome<T> {
+    pub outputs: Vec<T>,
+    pub error: Option<CpuError<T>>,
+    pub memory: Vec<T>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mismatch<T> {
+    pub program: Vec<T>,
+    pub inputs: Vec<T>,
+    pub backend: &'static str,
+    pub expected: Outcome<T>,
+    pub actual: Outcome<T>,
+}
+
+impl<T: Word> fmt::Display for Mismatch<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |values: &[T]| values.iter().map(T::to_string).collect::<Vec<_>>().join(",");
+        writeln!(f, "{} differs from the reference interpreter", self.backend)?;
+        writeln!(f, "program: {}", join(&self.program))?;
+        writeln!(f, " inputs: {}", join(&self.inputs))?;
+        writeln!(f, "expected: {:?}", self.expected)?;
+        write!(f, "  actual: {:?}", self.actual)
+    }
+}
+
+fn collect<T: Word>(
+    results: impl Iterator<Item = Result<T, CpuError<T>>>,
+    memory: impl Fn() -> Vec<T>,
+) -> Outcome<T> {
+    let mut outcome = Outcome { outputs: Vec::new(), error: None, memory: Vec::new() };
+    for result in results {
+        match result {
+            Ok(value) => outcome.outputs.push(value),
+            Err(err) => outcome.error = Some(err),
+        }
+    }
+    outcome.memory = memory();
+    outcome
+}
+
+fn interpret<T: Word, M: Memory<T>>(
+    mut cpu: CPU<T, BoundedMemory<M>>,
+    inputs: &[T],
+    config: &FuzzConfig,
+) -> Outcome<T> {
+    cpu.set_budget(Budget { instructions: Some(config.steps), ..Budget::default() });
+    let mut inputs = inputs.iter().cycle().cloned();
+    let results: Vec<_> = cpu.try_outputs_with(move || inputs.next().unwrap()).collect();
+    collect(results.into_iter(), || (0..config.memory).map(|index| cpu.mmu.get(index)).collect())
+}
+
+// Runs the program on every backend, the reference interpreter first
+pub fn run_backends<T: Word + 'static>(
+    program: &[T],
+    inputs: &[T],
+    config: &FuzzConfig,
+) -> Vec<(&'static str, Outcome<T>)> {
+    let dense = DenseMemory::from(program.to_vec());
+    let dense = CPU::with_memory(BoundedMemory::new(dense, config.memory));
+    let sparse = SparseMemory::from(program.to_vec());
+    let sparse = CPU::with_memory(BoundedMemory::new(sparse, config.memory));
+
+    let mut cached = dense.clone();
+    cached.enable_decode_cache();
+
+    let mut compiled = dense.compile();
+    compiled.set_budget(Some(config.steps));
+    let mut compiled_inputs = inputs.iter().cycle().cloned();
+    let results: Vec<_> =
+        compiled.try_outputs_with(move || compiled_inputs.next().unwrap()).collect();
+    let compiled = collect(results.into_iter(), || {
+        (0..config.memory).map(|index| compiled.get(index)).collect()
+    });
+
+    vec![
+        ("interpreter", interpret(dense, inputs, config)),
+        ("sparse memory", interpret(sparse, inputs, config)),
+        ("decode cache", interpret(cached, inputs, config)),
+        ("compiled", compiled),
+    ]
+}
+
+pub fn check<T: Word + 'static>(
+    program: &[T],
+    inputs: &[T],
+    config: &FuzzConfig,
+) -> Result<(), Box<Mismatch<T>>> {
+    let mut outcomes = run_backends(program, inputs, config).into_iter();
+    let (_, expected) = outcomes.next().unwrap();
+    for (backend, actual) in outcomes {
+        if actual != expected {
+            return Err(Box::new(Mismatch {
+                program: program.to_vec(),
+                inputs: inputs.to_vec(),
+                backend,
+                expected,
+                actual,
+            }));
+        }
+    }
+    Ok(())
+}
+
+// Checks `runs` random programs, stopping at the first one the backends disagree on
+pub fn fuzz(seed: u64, runs: usize, config: &FuzzConfig) -> Result<(), Box<Mismatch<i64>>> {
+    let mut rng = Rng::new(seed);
+    for _ in 0..runs {
+        let program = generate(&mut rng, config);
+        let inputs: Vec<_> =
+            (0..config.inputs.max(1)).map(|_| rng.between(-config.values, config.values)).collect();
+        check(&program, &inputs, config)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::Instruction;
+
+    #[test]
+    fn generates_well_formed_programs() {
+        let config = FuzzConfig::default();
+        let mut rng = Rng::new(7);
+        for _ in 0..100 {
+            let program: Vec<i64> = generate(&mut rng, &config);
+            assert_eq!(program.len(), config.memory);
+
+            // Every generated instruction decodes
+            let cpu = CPU::new(program.clone());
+            let mut pc = 0;
+            while pc < config.code {
+                let instruction = Instruction::decode(&cpu.mmu, pc).unwrap();
+                pc += instruction.len();
+            }
+        }
+        assert_eq!(Rng::new(3).next_u64(), Rng::new(3).next_u64());
+    }
+
+    #[test]
+    fn backends_agree() {
+        let config = FuzzConfig::default();
+        if let Err(mismatch) = fuzz(2019, 500, &config) {
+            panic!("{}", mismatch);
+        }
+
+        // Errors are compared too, e.g. running into data that isn't an instruction
+        let outcomes = run_backends(&[104, 5, 1106, 0, 5, 42], &[0], &config);
+        for (backend, outcome) in outcomes {
+            assert_eq!(outcome.outputs, vec![5], "{}", backend);
+            assert_eq!(
+                outcome.error,
+                Some(CpuError::InvalidOpcode { pc: 5, word: 42 }),
+                "{}",
+                backend
+            );
+        }
+    }
+
+    // cargo test --release fuzz_backends -- --ignored
+    #[test]
+    #[ignore]
+    fn fuzz_backends() {
+        let config = FuzzConfig { code: 200, memory: 400, steps: 100_000, ..FuzzConfig::default() };
+        for seed in 1..=20 {
+            if let Err(mismatch) = fuzz(seed, 1000, &config) {
+                panic!("seed {}: {}", seed, mismatch);
+            }
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::VecDeque;
+
+use super::memory::Memory;
+use super::{Word, CPU};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry<T> {
+    pub pc: usize,
+    pub rbo: isize,
+    // The address written by the instruction and the value it held before
+    pub overwritten: Option<(usize, T)>,
+    pub input: Option<T>,
+    pub output: Option<T>,
+}
+
+#[derive(Debug, Clone)]
+pub struct History<T> {
+    entries: VecDeque<HistoryEntry<T>>,
+    // Instructions executed before the oldest entry that is still kept
+    dropped: usize,
+    limit: Option<usize>,
+    awaiting_input: bool,
+}
+
+impl<T> History<T> {
+    fn new(limit: Option<usize>) -> History<T> {
+        History { entries: VecDeque::new(), dropped: 0, limit, awaiting_input: false }
+    }
+
+    pub(super) fn clear(&mut self) {
+        *self = History::new(self.limit);
+    }
+
+    pub(super) fn begin(&mut self, pc: usize, rbo: isize, reads_input: bool) {
+        // A read whose input never arrived didn't execute, and is about to be fetched again
+        if self.awaiting_input {
+            self.abandon();
+        }
+        self.awaiting_input = reads_input;
+
+        if self.limit.is_some_and(|limit| self.entries.len() >= limit) {
+            // With a limit of zero the entry is dropped straight away, so nothing is recorded
+            self.dropped += 1;
+            if self.entries.pop_front().is_none() {
+                return;
+            }
+        }
+        self.entries.push_back(HistoryEntry {
+            pc,
+            rbo,
+            overwritten: None,
+            input: None,
+            output: None,
+        });
+    }
+
+    // Forgets the latest entry, for an instruction that turned out not to execute
+    pub(super) fn abandon(&mut self) {
+        self.awaiting_input = false;
+        if self.entries.pop_back().is_none() {
+            self.dropped -= 1;
+        }
+    }
+
+    pub(super) fn record_write(&mut self, address: usize, old: T) {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.overwritten = Some((address, old));
+        }
+    }
+
+    pub(super) fn record_input(&mut self, address: usize, old: T, input: T) {
+        self.awaiting_input = false;
+        if let Some(entry) = self.entries.back_mut() {
+            entry.overwritten = Some((address, old));
+            entry.input = Some(input);
+        }
+    }
+
+    pub(super) fn record_output(&mut self, output: T) {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.output = Some(output);
+        }
+    }
+
+    pub fn executed(&self) -> usize {
+        self.dropped + self.entries.len()
+    }
+
+    pub fn oldest(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn entry(&self, step: usize) -> Option<&HistoryEntry<T>> {
+        self.entries.get(step.checked_sub(self.dropped)?)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (usize, &HistoryEntry<T>)> {
+        self.entries.iter().enumerate().map(move |(index, entry)| (self.dropped + index, entry))
+    }
+
+    pub fn last_write(&self, address: usize) -> Option<usize> {
+        let index = self.entries.iter().rposition(
+            |entry| matches!(entry.overwritten, Some((written, _)) if written == address),
+        )?;
+        Some(self.dropped + index)
+    }
+}
+
+impl<T, M> CPU<T, M> {
+    // Keeps an undo log of every executed instruction, or of the last `limit` ones
+    pub fn enable_history(&mut self, limit: Option<usize>) {
+        if self.history.is_none() {
+            self.history = Some(Box::new(History::new(limit)));
+        }
+    }
+
+    pub fn history(&self) -> Option<&History<T>> {
+        self.history.as_deref()
+    }
+
+    pub fn take_history(&mut self) -> Option<History<T>> {
+        self.history.take().map(|history| *history)
+    }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    // Undoes instructions until only `step` of them were executed. Returns the undone entries,
+    // latest first, or None if that step is outside the recorded history.
+    pub fn rewind_to(&mut self, step: usize) -> Option<Vec<HistoryEntry<T>>> {
+        let history = self.history.as_mut()?;
+        if step < history.oldest() || step > history.executed() {
+            return None;
+        }
+
+        let mut undone = Vec::new();
+        while history.executed() > step {
+            let entry = history.entries.pop_back().unwrap();
+            if let Some((address, old)) = &entry.overwritten {
+                *self.mmu.get_mut(*address) = old.clone();
+            }
+            self.pc = entry.pc;
+            self.rbo = entry.rbo;
+            undone.push(entry);
+        }
+
+        if !undone.is_empty() {
+            history.awaiting_input = false;
+            self.exit_code = None;
+        }
+        Some(undone)
+    }
+
+    pub fn step_back(&mut self, count: usize) -> Option<Vec<HistoryEntry<T>>> {
+        let executed = self.history.as_ref()?.executed();
+        self.rewind_to(executed.checked_sub(count)?)
+    }
+
+    // Rewinds to right before the latest instruction that wrote the address
+    pub fn rewind_to_last_write(&mut self, address: usize) -> Option<Vec<HistoryEntry<T>>> {
+        let step = self.history.as_ref()?.last_write(address)?;
+        self.rewind_to(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::resume::Status;
+    use crate::utils::intcode::{CpuError, ExecutionResult};
+    use itertools::Itertools;
+
+    // Reads a value, then outputs it while counting it down with the relative base
+    const COUNTDOWN: &str = "3,14,4,14,109,1,1001,14,-1,14,1005,14,2,99,0";
+
+    fn state(cpu: &CPU<i64>) -> (usize, isize, Vec<i64>) {
+        (cpu.pc(), cpu.rbo(), (0..cpu.mmu.len()).map(|index| cpu.mmu.get(index)).collect())
+    }
+
+    #[test]
+    fn rewinds_memory_and_registers() {
+        let mut cpu = CPU::<i64>::from_source(COUNTDOWN);
+        cpu.enable_history(None);
+
+        let mut states = vec![state(&cpu)];
+        let mut outputs = Vec::new();
+        loop {
+            let instruction = cpu.next_instruction().unwrap();
+            match cpu.execute_instruction(instruction) {
+                ExecutionResult::YieldedInput(sink) => sink(3),
+                ExecutionResult::YieldedOutput(value) => outputs.push(value),
+                ExecutionResult::Completed => break,
+                ExecutionResult::Running => {}
+            }
+            states.push(state(&cpu));
+        }
+        assert_eq!(outputs, vec![3, 2, 1]);
+
+        let history = cpu.history().unwrap();
+        assert_eq!(history.executed(), states.len());
+        assert_eq!(history.last_write(14), Some(history.executed() - 3));
+
+        for step in (0..states.len()).rev() {
+            cpu.rewind_to(step).unwrap();
+            assert_eq!(state(&cpu), states[step], "step {}", step);
+        }
+        assert!(cpu.rewind_to(1).is_none());
+    }
+
+    #[test]
+    fn resumes_with_different_inputs() {
+        let mut cpu = CPU::<i64>::from_source(COUNTDOWN);
+        cpu.enable_history(Some(4));
+        assert_eq!(cpu.outputs_with(|| 2).collect::<Vec<_>>(), vec![2, 1]);
+
+        // Only the last four instructions are kept, which don't include the read
+        assert_eq!(cpu.history().unwrap().oldest(), 6);
+        assert!(cpu.rewind_to_last_write(14).is_some());
+        assert!(cpu.rewind_to(3).is_none());
+
+        let mut cpu = CPU::<i64>::from_source(COUNTDOWN);
+        cpu.enable_history(None);
+        assert_eq!(cpu.outputs_with(|| 2).collect::<Vec<_>>(), vec![2, 1]);
+        while cpu.rewind_to_last_write(14).is_some() {}
+
+        let undone = cpu.step_back(0).unwrap();
+        assert!(undone.is_empty());
+        assert_eq!((cpu.pc(), cpu.history().unwrap().executed()), (0, 0));
+        assert_eq!(cpu.outputs_with(|| 4).collect::<Vec<_>>(), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn failed_instructions_are_not_recorded() {
+        // Moves the relative base below zero, then adds into it
+        let mut cpu = CPU::<i64>::from_source("109,-5,21101,1,1,0,99");
+        cpu.enable_history(None);
+        for _ in 0..2 {
+            assert_eq!(
+                cpu.try_outputs_with(|| 0).collect::<Vec<_>>(),
+                vec![Err(CpuError::NegativeAddress { pc: 2, word: 21101, address: -5 })]
+            );
+            assert_eq!(cpu.history().unwrap().executed(), 1);
+        }
+
+        let undone = cpu.step_back(1).unwrap();
+        assert_eq!(undone.len(), 1);
+        assert_eq!((cpu.pc(), cpu.rbo()), (0, 0));
+    }
+
+    #[test]
+    fn zero_limit_records_nothing() {
+        let mut cpu = CPU::<i64>::from_source(COUNTDOWN);
+        cpu.enable_history(Some(0));
+        assert_eq!(cpu.outputs_with(|| 2).collect::<Vec<_>>(), vec![2, 1]);
+
+        let history = cpu.history().unwrap();
+        assert_eq!(history.entries().count(), 0);
+        assert_eq!((history.oldest(), history.executed()), (10, 10));
+        assert!(cpu.step_back(1).is_none());
+
+        // Reads waiting for input aren't counted until the input arrives
+        let mut cpu = CPU::<i64>::from_source(COUNTDOWN);
+        cpu.enable_history(Some(0));
+        assert_eq!(
+            (cpu.try_run(), cpu.try_run()),
+            (Ok(Status::NeedsInput), Ok(Status::NeedsInput))
+        );
+        assert_eq!(cpu.history().unwrap().executed(), 0);
+        cpu.provide_input(1);
+        assert_eq!(cpu.try_run(), Ok(Status::Output(1)));
+        assert_eq!(cpu.history().unwrap().executed(), 2);
+    }
+
+    #[test]
+    fn replays_a_missed_ball() {
+        let mut cpu = CPU::<i64>::from_source(include_str!("../../day13/input"));
+        *cpu.mmu.get_mut(0) = 2;
+        cpu.enable_history(None);
+
+        // Without moving the paddle the ball is soon missed
+        let (mut ball, mut paddle) = (0, 0);
+        cpu.outputs_with(|| 0).for_each(drop);
+        let history = cpu.history().unwrap();
+        let moves = history.entries().filter(|(_, entry)| entry.input.is_some()).count();
+        let (first_move, _) = history.entries().find(|(_, entry)| entry.input.is_some()).unwrap();
+
+        // Going back to the first move and tracking the ball clears the board
+        let undone = cpu.rewind_to(first_move).unwrap();
+        assert_eq!(undone.iter().filter(|entry| entry.input.is_some()).count(), moves);
+        let outputs: Vec<_> =
+            cpu.history().unwrap().entries().filter_map(|(_, entry)| entry.output).collect();
+        for (x, _, tile) in outputs.into_iter().tuples() {
+            match tile {
+                3 => paddle = x,
+                4 => ball = x,
+                _ => {}
+            }
+        }
+
+        let mut score = 0;
+        let (ball, paddle) = (std::cell::Cell::new(ball), std::cell::Cell::new(paddle));
+        for (x, y, tile) in cpu.outputs_with(|| (ball.get() - paddle.get()).signum()).tuples() {
+            match (x, y, tile) {
+                (-1, 0, value) => score = value,
+                (x, _, 3) => paddle.set(x),
+                (x, _, 4) => ball.set(x),
+                _ => {}
+            }
+        }
+        assert_eq!(score, 15706);
+    }
+}
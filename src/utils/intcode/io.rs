@@ -0,0 +1,168 @@
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use super::memory::Memory;
+use super::resume::Status;
+use super::{CpuError, Word, CPU};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    Halted,
+    InputClosed,
+    OutputClosed,
+}
+
+// A closed input leaves the CPU waiting on its read instruction, so it can be resumed later with
+// another input source. Inputs go through the CPU's queue, so the read is only fetched and executed
+// once a value is there for it.
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    pub fn run_with_channels(
+        &mut self,
+        inputs: &Receiver<T>,
+        outputs: &Sender<T>,
+    ) -> Result<Stopped, CpuError<T>> {
+        loop {
+            match self.try_run()? {
+                Status::NeedsInput => match inputs.recv() {
+                    Ok(value) => self.provide_input(value),
+                    Err(_) => return Ok(Stopped::InputClosed),
+                },
+                Status::Output(value) => {
+                    if outputs.send(value).is_err() {
+                        return Ok(Stopped::OutputClosed);
+                    }
+                }
+                Status::Halted => return Ok(Stopped::Halted),
+            }
+        }
+    }
+
+    pub async fn run_async<I, O>(
+        &mut self,
+        mut inputs: I,
+        mut outputs: O,
+    ) -> Result<Stopped, CpuError<T>>
+    where
+        I: Stream<Item = T> + Unpin,
+        O: Sink<T> + Unpin,
+    {
+        loop {
+            match self.try_run()? {
+                Status::NeedsInput => match inputs.next().await {
+                    Some(value) => self.provide_input(value),
+                    None => return Ok(Stopped::InputClosed),
+                },
+                Status::Output(value) => {
+                    if outputs.send(value).await.is_err() {
+                        return Ok(Stopped::OutputClosed);
+                    }
+                }
+                Status::Halted => return Ok(Stopped::Halted),
+            }
+        }
+    }
+}
+
+impl<T, M> CPU<T, M>
+where
+    T: Word + Send + 'static,
+    M: Memory<T> + Send + 'static,
+{
+    pub fn spawn(
+        mut self,
+        inputs: Receiver<T>,
+        outputs: Sender<T>,
+    ) -> JoinHandle<Result<Stopped, CpuError<T>>> {
+        thread::spawn(move || self.run_with_channels(&inputs, &outputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::executor::block_on;
+    use futures::future::{join, join_all};
+    use std::cell::Cell;
+    use std::sync::mpsc::channel;
+
+    // Doubles every input until it reads a zero
+    const DOUBLER: &str = "3,15,1006,15,14,102,2,15,15,4,15,1105,1,0,99,0";
+
+    #[test]
+    fn runs_on_channels() {
+        let (input, inputs) = channel();
+        let (outputs, output) = channel();
+        let handle = CPU::<i64>::from_source(DOUBLER).spawn(inputs, outputs);
+
+        for value in 1..=3 {
+            input.send(value).unwrap();
+        }
+        drop(input);
+        assert_eq!(handle.join().unwrap(), Ok(Stopped::InputClosed));
+        assert_eq!(output.iter().collect::<Vec<_>>(), vec![2, 4, 6]);
+
+        let mut cpu = CPU::<i64>::from_source(DOUBLER);
+        let (input, inputs) = channel();
+        let (outputs, output) = channel();
+        input.send(5).unwrap();
+        input.send(0).unwrap();
+        assert_eq!(cpu.run_with_channels(&inputs, &outputs), Ok(Stopped::Halted));
+        assert_eq!(output.try_iter().collect::<Vec<_>>(), vec![10]);
+    }
+
+    #[test]
+    fn closed_inputs_resume_cleanly() {
+        let mut cpu = CPU::<i64>::from_source(DOUBLER);
+        cpu.enable_profiling();
+        cpu.start_trace();
+
+        let (input, inputs) = mpsc::unbounded();
+        let (outputs, output) = mpsc::unbounded();
+        input.unbounded_send(3).unwrap();
+        drop(input);
+        assert_eq!(block_on(cpu.run_async(inputs, outputs)), Ok(Stopped::InputClosed));
+        assert_eq!(block_on(output.collect::<Vec<_>>()), vec![6]);
+
+        let (input, inputs) = channel();
+        let (outputs, _output) = channel();
+        input.send(0).unwrap();
+        drop(input);
+        assert_eq!(cpu.run_with_channels(&inputs, &outputs), Ok(Stopped::Halted));
+
+        // Both reads at 0 ran once each, and nothing was traced for the reads that found no input
+        assert_eq!(cpu.profile().unwrap().hits(0), 2);
+        assert_eq!(cpu.stop_trace().len(), 8);
+    }
+
+    #[test]
+    fn runs_on_streams() {
+        let program = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,\
+                       28,6,99,0,0,5";
+
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..5).map(|_| mpsc::unbounded()).unzip();
+        for (sender, &phase) in senders.iter().zip(&[9, 8, 7, 6, 5]) {
+            sender.unbounded_send(phase).unwrap();
+        }
+        senders[0].unbounded_send(0).unwrap();
+
+        // The last amplifier reports to the test, which feeds its signals back to the first one
+        let (signals, received) = mpsc::unbounded();
+        let feedback = senders[0].clone();
+        let outputs = senders.into_iter().skip(1).chain(Some(signals));
+
+        let mut cpus: Vec<_> = (0..5).map(|_| CPU::<i64>::from_source(program)).collect();
+        let machines = cpus
+            .iter_mut()
+            .zip(receivers.into_iter().zip(outputs))
+            .map(|(cpu, (inputs, outputs))| cpu.run_async(inputs, outputs));
+
+        let last = Cell::new(0);
+        let forward = received.inspect(|&signal| last.set(signal)).map(Ok).forward(feedback);
+        let (results, _) = block_on(join(join_all(machines), forward));
+
+        assert!(results.iter().all(|result| *result == Ok(Stopped::Halted)), "{:?}", results);
+        assert_eq!(last.get(), 139_629_729);
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::Word;
+
+const PAGE_SIZE: usize = 64;
+
+// How far past the end a dense table grows before addresses go into a map instead
+pub(super) const MAX_GAP: usize = 1 << 16;
+
+pub trait Memory<T>: Clone {
+    fn len(&self) -> usize;
+
+    fn get(&self, index: usize) -> T;
+
+    // None when the address is past the memory limit
+    fn get_mut(&mut self, index: usize) -> Option<&mut T>;
+
+    // Every stored cell in address order. Cells left out are zero, so walking these instead of
+    // 0..len() stays cheap when a single write lands far away.
+    fn cells(&self) -> Box<dyn Iterator<Item = (usize, T)> + '_>;
+
+    fn limit(&self) -> Option<usize> {
+        None
+    }
+}
+
+fn split_pages<T>(memory: Vec<T>) -> impl Iterator<Item = Arc<Vec<T>>> {
+    let mut words = memory.into_iter().peekable();
+    std::iter::from_fn(move || {
+        words.peek()?;
+        Some(Arc::new(words.by_ref().take(PAGE_SIZE).collect()))
+    })
+}
+
+fn page_cells<'a, T: Clone + 'a>(
+    pages: impl Iterator<Item = (usize, &'a Arc<Vec<T>>)> + 'a,
+) -> Box<dyn Iterator<Item = (usize, T)> + 'a> {
+    Box::new(pages.flat_map(|(page, words)| {
+        words
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(move |(offset, word)| (page * PAGE_SIZE + offset, word))
+    }))
+}
+
+fn page_mut<T: Word>(page: &mut Arc<Vec<T>>, offset: usize) -> &mut T {
+    let page = Arc::make_mut(page);
+    if page.len() <= offset {
+        page.resize_with(offset + 1, T::zero);
+    }
+    &mut page[offset]
+}
+
+// Memory is split into reference counted pages so that cloned CPUs and snapshots only copy the
+// pages they write to.
+#[derive(Debug, Clone)]
+pub struct DenseMemory<T> {
+    pages: Vec<Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T> From<Vec<T>> for DenseMemory<T> {
+    fn from(memory: Vec<T>) -> Self {
+        let len = memory.len();
+        DenseMemory { pages: split_pages(memory).collect(), len }
+    }
+}
+
+impl<T> DenseMemory<T> {
+    pub fn shared_pages(&self, other: &DenseMemory<T>) -> usize {
+        self.pages.iter().zip(&other.pages).filter(|(a, b)| Arc::ptr_eq(a, b)).count()
+    }
+}
+
+impl<T: Word> Memory<T> for DenseMemory<T> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> T {
+        self.pages
+            .get(index / PAGE_SIZE)
+            .and_then(|page| page.get(index % PAGE_SIZE))
+            .cloned()
+            .unwrap_or_else(T::zero)
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let page = index / PAGE_SIZE;
+        if self.pages.len() <= page {
+            self.pages.resize_with(page + 1, Default::default);
+        }
+        self.len = self.len.max(index + 1);
+        Some(page_mut(&mut self.pages[page], index % PAGE_SIZE))
+    }
+
+    fn cells(&self) -> Box<dyn Iterator<Item = (usize, T)> + '_> {
+        page_cells(self.pages.iter().enumerate())
+    }
+}
+
+// Only the pages that are actually touched are allocated, so a single write far away through
+// relative mode doesn't grow the whole page table.
+#[derive(Debug, Clone)]
+pub struct SparseMemory<T> {
+    pages: HashMap<usize, Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T> From<Vec<T>> for SparseMemory<T> {
+    fn from(memory: Vec<T>) -> Self {
+        let len = memory.len();
+        SparseMemory { pages: split_pages(memory).enumerate().collect(), len }
+    }
+}
+
+impl<T> SparseMemory<T> {
+    pub fn allocated_pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+impl<T: Word> Memory<T> for SparseMemory<T> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> T {
+        self.pages
+            .get(&(index / PAGE_SIZE))
+            .and_then(|page| page.get(index % PAGE_SIZE))
+            .cloned()
+            .unwrap_or_else(T::zero)
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.len = self.len.max(index + 1);
+        Some(page_mut(self.pages.entry(index / PAGE_SIZE).or_default(), index % PAGE_SIZE))
+    }
+
+    fn cells(&self) -> Box<dyn Iterator<Item = (usize, T)> + '_> {
+        let mut pages: Vec<_> = self.pages.iter().map(|(&page, words)| (page, words)).collect();
+        pages.sort_unstable_by_key(|&(page, _)| page);
+        page_cells(pages.into_iter())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BoundedMemory<M> {
+    inner: M,
+    limit: usize,
+}
+
+impl<M> BoundedMemory<M> {
+    pub fn new(inner: M, limit: usize) -> BoundedMemory<M> {
+        BoundedMemory { inner, limit }
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+impl<T, M: Memory<T>> Memory<T> for BoundedMemory<M> {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn get(&self, index: usize) -> T {
+        self.inner.get(index)
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index < self.limit {
+            self.inner.get_mut(index)
+        } else {
+            None
+        }
+    }
+
+    fn cells(&self) -> Box<dyn Iterator<Item = (usize, T)> + '_> {
+        self.inner.cells()
+    }
+
+    fn limit(&self) -> Option<usize> {
+        Some(self.inner.limit().map_or(self.limit, |limit| limit.min(self.limit)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::{parse_program, CpuError, CPU};
+
+    // Writes the input through relative mode at 2^40 + 1, reads it back and outputs it
+    const FAR_WRITE: &str = "109,1099511627776,203,1,204,1,99";
+
+    #[test]
+    fn backends_behave_the_same() {
+        let program: Vec<i64> = parse_program(include_str!("../../day09/input"));
+
+        let dense = CPU::new(program.clone()).outputs_with(|| 1).last();
+        let sparse =
+            CPU::with_memory(SparseMemory::from(program.clone())).outputs_with(|| 1).last();
+        let bounded = CPU::with_memory(BoundedMemory::new(DenseMemory::from(program), 2048))
+            .outputs_with(|| 1)
+            .last();
+
+        assert_eq!(dense, Some(2_752_191_671));
+        assert_eq!(sparse, dense);
+        assert_eq!(bounded, dense);
+    }
+
+    #[test]
+    fn sparse_memory_only_allocates_touched_pages() {
+        let mut cpu = CPU::with_memory(SparseMemory::from(parse_program::<i64>(FAR_WRITE)));
+        assert_eq!(cpu.outputs_with(|| 42).collect::<Vec<_>>(), vec![42]);
+        assert_eq!(cpu.mmu.memory.allocated_pages(), 2);
+        assert_eq!(cpu.mmu.len(), (1 << 40) + 2);
+
+        let cells: Vec<_> = cpu.mmu.cells().filter(|(_, value)| *value != 0).collect();
+        assert_eq!(cells.last(), Some(&((1 << 40) + 1, 42)));
+        assert_eq!(cells.len(), 8);
+
+        let mut compiled = CPU::<i64>::from_source(FAR_WRITE).compile();
+        assert_eq!(compiled.outputs_with(|| 42).collect::<Vec<_>>(), vec![42]);
+        assert_eq!(compiled.get((1 << 40) + 1), 42);
+    }
+
+    #[test]
+    fn bounded_memory_reports_errors() {
+        let memory = BoundedMemory::new(SparseMemory::from(parse_program::<i64>(FAR_WRITE)), 1024);
+        let mut cpu = CPU::with_memory(memory);
+        assert_eq!(
+            cpu.try_outputs_with(|| 42).collect::<Vec<_>>(),
+            vec![Err(CpuError::AddressOutOfBounds { pc: 2, word: 203, address: (1 << 40) + 1 })]
+        );
+
+        let memory =
+            BoundedMemory::new(DenseMemory::from(parse_program::<i64>("1105,1,4096")), 1024);
+        assert_eq!(
+            CPU::with_memory(memory).try_outputs_with(|| 0).collect::<Vec<_>>(),
+            vec![Err(CpuError::JumpOutOfRange { pc: 0, word: 1105, target: 4096 })]
+        );
+
+        let mut memory = BoundedMemory::new(DenseMemory::from(vec![0i64; 4]), 8);
+        assert!(memory.get_mut(7).is_some());
+        assert!(memory.get_mut(8).is_none());
+        assert_eq!(memory.len(), 8);
+    }
+
+    #[test]
+    fn pages_are_shared_between_clones() {
+        let original = DenseMemory::from((0..1000i64).collect::<Vec<_>>());
+        let mut copy = original.clone();
+        *copy.get_mut(500).unwrap() = -1;
+
+        assert_eq!(original.get(500), 500);
+        assert_eq!(copy.get(500), -1);
+        assert_eq!(copy.shared_pages(&original), original.shared_pages(&original) - 1);
+    }
+}
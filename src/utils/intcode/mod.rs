@@ -0,0 +1,867 @@
+use std::error::Error;
+use std::fmt;
+use std::iter::from_fn;
+use std::str::FromStr;
+
+#[allow(dead_code)]
+pub mod ascii;
+#[allow(dead_code)]
+pub mod asm;
+#[allow(dead_code)]
+pub mod budget;
+#[allow(dead_code)]
+pub mod cache;
+#[allow(dead_code)]
+pub mod compile;
+#[cfg(test)]
+mod conformance;
+#[allow(dead_code)]
+pub mod coverage;
+#[allow(dead_code)]
+pub mod debugger;
+#[allow(dead_code)]
+pub mod disasm;
+#[allow(dead_code)]
+pub mod extension;
+#[allow(dead_code)]
+pub mod flow;
+#[allow(dead_code)]
+pub mod fuzz;
+#[allow(dead_code)]
+pub mod history;
+#[allow(dead_code)]
+pub mod io;
+#[allow(dead_code)]
+pub mod memory;
+#[allow(dead_code)]
+pub mod network;
+#[allow(dead_code)]
+pub mod profile;
+#[allow(dead_code)]
+pub mod resume;
+#[allow(dead_code)]
+pub mod snapshot;
+#[allow(dead_code)]
+pub mod symbolic;
+#[allow(dead_code)]
+pub mod topology;
+#[allow(dead_code)]
+pub mod trace;
+#[allow(dead_code)]
+pub mod watch;
+#[allow(dead_code)]
+pub mod word;
+
+use budget::Limits;
+use cache::DecodeCache;
+use coverage::Coverage;
+use extension::{Effect, Extensions};
+use history::History;
+use memory::{DenseMemory, Memory};
+use profile::Profile;
+use resume::Queues;
+use trace::TraceEntry;
+use watch::{Access, WatchEvent, Watchpoint};
+pub use word::{Arithmetic, Word};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Parameter<T> {
+    Immediate(T),
+    Indexed(usize),
+    Relative(isize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError<T> {
+    InvalidOpcode { pc: usize, word: T },
+    InvalidMode { pc: usize, word: T, mode: usize },
+    WriteToImmediate { pc: usize, word: T },
+    NegativeAddress { pc: usize, word: T, address: isize },
+    JumpOutOfRange { pc: usize, word: T, target: T },
+    AddressOutOfBounds { pc: usize, word: T, address: usize },
+    Overflow { pc: usize, word: T },
+    ExtensionFailed { pc: usize, word: T },
+    BudgetExhausted { pc: usize, word: T },
+    InfiniteLoop { pc: usize, word: T },
+}
+
+#[allow(dead_code)]
+impl<T: Clone> CpuError<T> {
+    pub fn pc(&self) -> usize {
+        match *self {
+            Self::InvalidOpcode { pc, .. }
+            | Self::InvalidMode { pc, .. }
+            | Self::WriteToImmediate { pc, .. }
+            | Self::NegativeAddress { pc, .. }
+            | Self::JumpOutOfRange { pc, .. }
+            | Self::AddressOutOfBounds { pc, .. }
+            | Self::Overflow { pc, .. }
+            | Self::ExtensionFailed { pc, .. }
+            | Self::BudgetExhausted { pc, .. }
+            | Self::InfiniteLoop { pc, .. } => pc,
+        }
+    }
+
+    pub fn word(&self) -> T {
+        match self {
+            Self::InvalidOpcode { word, .. }
+            | Self::InvalidMode { word, .. }
+            | Self::WriteToImmediate { word, .. }
+            | Self::NegativeAddress { word, .. }
+            | Self::JumpOutOfRange { word, .. }
+            | Self::AddressOutOfBounds { word, .. }
+            | Self::Overflow { word, .. }
+            | Self::ExtensionFailed { word, .. }
+            | Self::BudgetExhausted { word, .. }
+            | Self::InfiniteLoop { word, .. } => word.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for CpuError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidOpcode { pc, word } => {
+                write!(f, "Invalid opcode in {} at {}", word, pc)
+            }
+            Self::InvalidMode { pc, word, mode } => {
+                write!(f, "Invalid parameter mode {} in {} at {}", mode, word, pc)
+            }
+            Self::WriteToImmediate { pc, word } => {
+                write!(f, "Can't write an immediate parameter in {} at {}", word, pc)
+            }
+            Self::NegativeAddress { pc, word, address } => {
+                write!(f, "Negative address {} in {} at {}", address, word, pc)
+            }
+            Self::JumpOutOfRange { pc, word, target } => {
+                write!(f, "Jump target {} out of range in {} at {}", target, word, pc)
+            }
+            Self::AddressOutOfBounds { pc, word, address } => {
+                write!(f, "Address {} out of bounds in {} at {}", address, word, pc)
+            }
+            Self::Overflow { pc, word } => write!(f, "Arithmetic overflow in {} at {}", word, pc),
+            Self::ExtensionFailed { pc, word } => {
+                write!(f, "Extension instruction failed in {} at {}", word, pc)
+            }
+            Self::BudgetExhausted { pc, word } => {
+                write!(f, "Instruction budget exhausted in {} at {}", word, pc)
+            }
+            Self::InfiniteLoop { pc, word } => write!(f, "Infinite loop in {} at {}", word, pc),
+        }
+    }
+}
+
+impl<T: fmt::Debug + fmt::Display> Error for CpuError<T> {}
+
+impl<T: fmt::Display> fmt::Display for Parameter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Immediate(val) => write!(f, "IM({})", val),
+            Self::Indexed(val) => write!(f, "IN({})", val),
+            Self::Relative(val) => write!(f, "RE({})", val),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Instruction<T> {
+    Add(Parameter<T>, Parameter<T>, Parameter<T>),
+    Multiply(Parameter<T>, Parameter<T>, Parameter<T>),
+    Read(Parameter<T>),
+    Write(Parameter<T>),
+    JumpIfTrue(Parameter<T>, Parameter<T>),
+    JumpIfFalse(Parameter<T>, Parameter<T>),
+    LessThan(Parameter<T>, Parameter<T>, Parameter<T>),
+    Equals(Parameter<T>, Parameter<T>, Parameter<T>),
+    RelativeBaseOffset(Parameter<T>),
+    Break,
+    Extended { opcode: usize, mnemonic: &'static str, params: Vec<Parameter<T>> },
+}
+
+impl<T: Word> Instruction<T> {
+    pub fn decode<M: Memory<T>>(mmu: &MMU<T, M>, pc: usize) -> Result<Instruction<T>, CpuError<T>> {
+        Instruction::decode_with(|index| mmu.get(index), pc, &mmu.extensions)
+    }
+
+    pub fn decode_with(
+        get: impl Fn(usize) -> T,
+        pc: usize,
+        extensions: &Extensions<T>,
+    ) -> Result<Instruction<T>, CpuError<T>> {
+        let word = get(pc);
+        let value =
+            word.to_usize().ok_or_else(|| CpuError::InvalidOpcode { pc, word: word.clone() })?;
+        let opcode = value % 100;
+
+        let param = |offset: usize, divisor: usize| {
+            let param = get(pc + offset);
+            match (value / divisor) % 10 {
+                0 => match param.to_usize() {
+                    Some(index) => Ok(Parameter::Indexed(index)),
+                    None => Err(CpuError::NegativeAddress {
+                        pc,
+                        word: word.clone(),
+                        address: param.to_isize().unwrap_or(isize::MIN),
+                    }),
+                },
+                1 => Ok(Parameter::Immediate(param)),
+                2 => match param.to_isize() {
+                    Some(offset) => Ok(Parameter::Relative(offset)),
+                    None => Err(CpuError::Overflow { pc, word: word.clone() }),
+                },
+                mode => Err(CpuError::InvalidMode { pc, word: word.clone(), mode }),
+            }
+        };
+
+        let p0 = || param(1, 100);
+        let p1 = || param(2, 1000);
+        let p2 = || param(3, 10000);
+
+        let instruction = match opcode {
+            1 => Instruction::Add(p0()?, p1()?, p2()?),
+            2 => Instruction::Multiply(p0()?, p1()?, p2()?),
+            3 => Instruction::Read(p0()?),
+            4 => Instruction::Write(p0()?),
+            5 => Instruction::JumpIfTrue(p0()?, p1()?),
+            6 => Instruction::JumpIfFalse(p0()?, p1()?),
+            7 => Instruction::LessThan(p0()?, p1()?, p2()?),
+            8 => Instruction::Equals(p0()?, p1()?, p2()?),
+            9 => Instruction::RelativeBaseOffset(p0()?),
+            99 => Instruction::Break,
+            _ => match extensions.get(opcode) {
+                Some(extension) => Instruction::Extended {
+                    opcode,
+                    mnemonic: extension.mnemonic(),
+                    params: (0..extension.arity())
+                        .map(|index| param(index + 1, 10usize.pow(index as u32 + 2)))
+                        .collect::<Result<_, _>>()?,
+                },
+                None => return Err(CpuError::InvalidOpcode { pc, word }),
+            },
+        };
+
+        Ok(instruction)
+    }
+
+    #[allow(dead_code)]
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Add(..) => "ADD",
+            Self::Multiply(..) => "MUL",
+            Self::Read(..) => "RED",
+            Self::Write(..) => "WRT",
+            Self::JumpIfTrue(..) => "JIT",
+            Self::JumpIfFalse(..) => "JIF",
+            Self::LessThan(..) => "LST",
+            Self::Equals(..) => "EQL",
+            Self::RelativeBaseOffset(..) => "RBO",
+            Self::Break => "BRK",
+            Self::Extended { mnemonic, .. } => mnemonic,
+        }
+    }
+
+    // The canonical opcode word. Decoding ignores mode digits past the last parameter, so a word
+    // like 10099 decodes to an instruction that encodes back to 99.
+    pub fn encode(&self) -> T {
+        let (opcode, params) = match self {
+            Self::Add(a, b, c) => (1, vec![a, b, c]),
+            Self::Multiply(a, b, c) => (2, vec![a, b, c]),
+            Self::Read(a) => (3, vec![a]),
+            Self::Write(a) => (4, vec![a]),
+            Self::JumpIfTrue(a, b) => (5, vec![a, b]),
+            Self::JumpIfFalse(a, b) => (6, vec![a, b]),
+            Self::LessThan(a, b, c) => (7, vec![a, b, c]),
+            Self::Equals(a, b, c) => (8, vec![a, b, c]),
+            Self::RelativeBaseOffset(a) => (9, vec![a]),
+            Self::Break => (99, vec![]),
+            Self::Extended { opcode, params, .. } => (*opcode, params.iter().collect()),
+        };
+        let modes = params.iter().rev().fold(0, |modes, param| {
+            modes * 10
+                + match param {
+                    Parameter::Indexed(_) => 0,
+                    Parameter::Immediate(_) => 1,
+                    Parameter::Relative(_) => 2,
+                }
+        });
+        T::from_usize(opcode + modes * 100).unwrap()
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Add(..) | Self::Multiply(..) | Self::LessThan(..) | Self::Equals(..) => 4,
+            Self::JumpIfTrue(..) | Self::JumpIfFalse(..) => 3,
+            Self::Read(..) | Self::Write(..) | Self::RelativeBaseOffset(..) => 2,
+            Self::Break => 1,
+            Self::Extended { params, .. } => params.len() + 1,
+        }
+    }
+}
+
+impl<T: Word> fmt::Display for Instruction<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Add(a, b, c) => write!(f, "ADD {}, {}, {}", a, b, c),
+            Self::Multiply(a, b, c) => write!(f, "MUL {}, {}, {}", a, b, c),
+            Self::Read(a) => write!(f, "RED {}", a),
+            Self::Write(a) => write!(f, "WRT {}", a),
+            Self::JumpIfTrue(a, b) => write!(f, "JIT {}, {}", a, b),
+            Self::JumpIfFalse(a, b) => write!(f, "JIF {}, {}", a, b),
+            Self::LessThan(a, b, c) => write!(f, "LST {}, {}, {}", a, b, c),
+            Self::Equals(a, b, c) => write!(f, "EQL {}, {}, {}", a, b, c),
+            Self::RelativeBaseOffset(a) => write!(f, "RBO {}", a),
+            Self::Break => write!(f, "BRK"),
+            Self::Extended { mnemonic, params, .. } => {
+                write!(f, "{}", mnemonic)?;
+                for (index, param) in params.iter().enumerate() {
+                    write!(f, "{}{}", if index == 0 { " " } else { ", " }, param)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub enum ExecutionResult<T, Sink: FnOnce(T)> {
+    Running,
+    YieldedOutput(T),
+    YieldedInput(Sink),
+    Completed,
+}
+
+// What an instruction did. Unlike ExecutionResult it doesn't borrow the CPU, so a failed
+// instruction can still be cleaned up after.
+enum Executed<T> {
+    Running,
+    Output(T),
+    Input(usize),
+    Completed,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct MMU<T, M = DenseMemory<T>> {
+    memory: M,
+    watchpoints: Vec<Watchpoint>,
+    events: Vec<WatchEvent<T>>,
+    cache: Option<DecodeCache<T>>,
+    extensions: Extensions<T>,
+    // XOR of the hashes of every written cell, kept up to date while loop detection is enabled
+    fingerprint: Option<u64>,
+}
+
+impl<T, M> MMU<T, M> {
+    fn new(memory: M) -> MMU<T, M> {
+        MMU {
+            memory,
+            watchpoints: Vec::new(),
+            events: Vec::new(),
+            cache: None,
+            extensions: Extensions::new(),
+            fingerprint: None,
+        }
+    }
+}
+
+impl<T: Word, M: Memory<T>> MMU<T, M> {
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = (usize, T)> + '_ {
+        self.memory.cells()
+    }
+
+    // Everything up to the last non-zero cell, which is all a reader defaulting to zero needs
+    pub fn to_vec(&self) -> Vec<T> {
+        let mut memory = Vec::new();
+        for (index, value) in self.cells().filter(|(_, value)| !value.is_zero()) {
+            memory.resize_with(index, T::zero);
+            memory.push(value);
+        }
+        memory
+    }
+
+    pub fn get(&self, index: usize) -> T {
+        self.memory.get(index)
+    }
+
+    // Changes made through the returned reference can't be tracked, so the fingerprint is
+    // recomputed the next time it's needed
+    pub fn get_mut(&mut self, index: usize) -> &mut T {
+        self.fingerprint = None;
+        let limit = self.limit();
+        self.cell(index).unwrap_or_else(|| {
+            panic!("Address {} exceeds the memory limit {}", index, limit.unwrap_or(0))
+        })
+    }
+
+    fn cell(&mut self, index: usize) -> Option<&mut T> {
+        if let Some(cache) = &mut self.cache {
+            cache.invalidate(index, self.extensions.longest_instruction());
+        }
+        self.memory.get_mut(index)
+    }
+
+    fn limit(&self) -> Option<usize> {
+        self.memory.limit()
+    }
+
+    fn read(&mut self, index: usize, pc: usize) -> T {
+        let value = self.get(index);
+        if !self.watchpoints.is_empty() {
+            self.record(Access::Read, index, pc, &value, &value);
+        }
+        value
+    }
+
+    fn write(&mut self, index: usize, value: T, pc: usize) -> Option<()> {
+        if self.watchpoints.is_empty() && self.fingerprint.is_none() {
+            *self.cell(index)? = value;
+        } else {
+            let old = std::mem::replace(self.cell(index)?, value.clone());
+            if let Some(fingerprint) = &mut self.fingerprint {
+                *fingerprint ^= budget::cell_hash(index, &old) ^ budget::cell_hash(index, &value);
+            }
+            if !self.watchpoints.is_empty() {
+                self.record(Access::Write, index, pc, &old, &value);
+            }
+        }
+        Some(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CPU<T, M = DenseMemory<T>> {
+    pc: usize,
+    rbo: isize,
+    // Boxed, along with state that's only there while it's enabled, so that CPUs stay cheap
+    // to move around
+    pub(crate) mmu: Box<MMU<T, M>>,
+    trace: Option<Vec<TraceEntry<T>>>,
+    arithmetic: Arithmetic,
+    exit_code: Option<T>,
+    limits: Option<Box<Limits>>,
+    profile: Option<Box<Profile>>,
+    coverage: Option<Box<Coverage>>,
+    history: Option<Box<History<T>>>,
+    queues: Box<Queues<T>>,
+}
+
+pub fn parse_program<T: FromStr>(input: &str) -> Vec<T> {
+    input
+        .trim()
+        .split(',')
+        .map(|s| s.parse().ok().unwrap_or_else(|| panic!("Failed parsing {}", s)))
+        .collect()
+}
+
+impl<T> CPU<T> {
+    pub fn new(memory: Vec<T>) -> CPU<T> {
+        CPU::with_memory(DenseMemory::from(memory))
+    }
+}
+
+impl<T: FromStr> CPU<T> {
+    pub fn from_source(input: &str) -> CPU<T> {
+        CPU::new(parse_program(input))
+    }
+}
+
+impl<T, M> CPU<T, M> {
+    pub fn with_memory(memory: M) -> CPU<T, M> {
+        CPU {
+            pc: 0,
+            rbo: 0,
+            mmu: Box::new(MMU::new(memory)),
+            trace: None,
+            arithmetic: Arithmetic::default(),
+            exit_code: None,
+            limits: None,
+            profile: None,
+            coverage: None,
+            history: None,
+            queues: Box::default(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    #[allow(dead_code)]
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    #[allow(dead_code)]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    #[allow(dead_code)]
+    pub fn rbo(&self) -> isize {
+        self.rbo
+    }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    pub fn outputs(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.outputs_with(|| panic!("No input provided!"))
+    }
+
+    pub fn outputs_with<'a, 'b: 'a>(
+        &'a mut self,
+        mut get_input: impl FnMut() -> T + 'b,
+    ) -> impl Iterator<Item = T> + '_ {
+        from_fn(move || loop {
+            let instruction = match self.next_instruction() {
+                Ok(instruction) => instruction,
+                Err(CpuError::InvalidOpcode { word, .. }) if word.is_negative() => break None,
+                Err(err) => panic!("{}", err),
+            };
+            match self.execute_instruction(instruction) {
+                ExecutionResult::YieldedInput(sink) => sink(get_input()),
+                ExecutionResult::YieldedOutput(value) => break Some(value),
+                ExecutionResult::Completed => break None,
+                ExecutionResult::Running => {}
+            }
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn try_outputs_with<'a, 'b: 'a>(
+        &'a mut self,
+        mut get_input: impl FnMut() -> T + 'b,
+    ) -> impl Iterator<Item = Result<T, CpuError<T>>> + 'a {
+        let mut failed = false;
+        from_fn(move || {
+            if failed {
+                return None;
+            }
+            let result = loop {
+                let instruction = match self.next_instruction() {
+                    Ok(instruction) => instruction,
+                    Err(err) => break Some(Err(err)),
+                };
+                match self.try_execute_instruction(instruction) {
+                    Ok(ExecutionResult::YieldedInput(sink)) => sink(get_input()),
+                    Ok(ExecutionResult::YieldedOutput(value)) => break Some(Ok(value)),
+                    Ok(ExecutionResult::Completed) => break None,
+                    Ok(ExecutionResult::Running) => {}
+                    Err(err) => break Some(Err(err)),
+                }
+            };
+            failed = matches!(result, Some(Err(_)));
+            result
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn fetch_instruction(&self) -> Option<Instruction<T>> {
+        self.mmu.get(self.pc).to_usize()?;
+        Some(self.try_fetch_instruction().unwrap_or_else(|err| panic!("{}", err)))
+    }
+
+    pub fn try_fetch_instruction(&self) -> Result<Instruction<T>, CpuError<T>> {
+        Instruction::decode(&self.mmu, self.pc)
+    }
+
+    pub fn execute_instruction(
+        &mut self,
+        instruction: Instruction<T>,
+    ) -> ExecutionResult<T, impl FnOnce(T) + '_> {
+        self.try_execute_instruction(instruction).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_execute_instruction(
+        &mut self,
+        instruction: Instruction<T>,
+    ) -> Result<ExecutionResult<T, impl FnOnce(T) + '_>, CpuError<T>> {
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEntry::new(self.pc, instruction.clone()));
+        }
+        if let Some(history) = &mut self.history {
+            history.begin(self.pc, self.rbo, matches!(instruction, Instruction::Read(_)));
+        }
+
+        let executed = match self.execute(instruction) {
+            Ok(executed) => executed,
+            Err(err) => {
+                // Failed instructions have no effects, so there's nothing to rewind or trace
+                if let Some(history) = &mut self.history {
+                    history.abandon();
+                }
+                if let Some(trace) = &mut self.trace {
+                    trace.pop();
+                }
+                return Err(err);
+            }
+        };
+
+        Ok(match executed {
+            Executed::Running => ExecutionResult::Running,
+            Executed::Output(value) => ExecutionResult::YieldedOutput(value),
+            Executed::Completed => ExecutionResult::Completed,
+            Executed::Input(dest) => ExecutionResult::YieldedInput(move |result: T| {
+                if let Some(entry) = self.trace_entry() {
+                    entry.input = Some(result.clone());
+                    entry.write = Some((dest, result.clone()));
+                }
+                if let Some(history) = &mut self.history {
+                    history.record_input(dest, self.mmu.get(dest), result.clone());
+                }
+                // The address was already checked against the limit by get_address
+                if self.mmu.write(dest, result, self.pc).is_some() {
+                    self.pc += 2;
+                }
+            }),
+        })
+    }
+
+    fn execute(&mut self, instruction: Instruction<T>) -> Result<Executed<T>, CpuError<T>> {
+        let executed = match instruction {
+            Instruction::Add(first, second, dest) => {
+                let (first, second) = (self.get_param(first)?, self.get_param(second)?);
+                let value = self.arithmetic.add(&first, &second).ok_or_else(|| self.overflow())?;
+                self.set_param(dest, value)?;
+                self.pc += 4;
+                Executed::Running
+            }
+            Instruction::Multiply(first, second, dest) => {
+                let (first, second) = (self.get_param(first)?, self.get_param(second)?);
+                let value = self.arithmetic.mul(&first, &second).ok_or_else(|| self.overflow())?;
+                self.set_param(dest, value)?;
+                self.pc += 4;
+                Executed::Running
+            }
+            Instruction::Read(dest) => Executed::Input(self.get_address(dest)?),
+            Instruction::Write(param) => {
+                let value = self.get_param(param)?;
+                if let Some(entry) = self.trace_entry() {
+                    entry.output = Some(value.clone());
+                }
+                if let Some(history) = &mut self.history {
+                    history.record_output(value.clone());
+                }
+                self.pc += 2;
+                Executed::Output(value)
+            }
+            Instruction::JumpIfTrue(cond, param) => {
+                if !self.get_param(cond)?.is_zero() {
+                    self.pc = self.get_target(param)?;
+                } else {
+                    self.pc += 3;
+                };
+                Executed::Running
+            }
+            Instruction::JumpIfFalse(cond, param) => {
+                if self.get_param(cond)?.is_zero() {
+                    self.pc = self.get_target(param)?;
+                } else {
+                    self.pc += 3;
+                };
+                Executed::Running
+            }
+            Instruction::LessThan(first, second, dest) => {
+                let value = if self.get_param(first)? < self.get_param(second)? {
+                    T::one()
+                } else {
+                    T::zero()
+                };
+                self.set_param(dest, value)?;
+                self.pc += 4;
+                Executed::Running
+            }
+            Instruction::Equals(first, second, dest) => {
+                let value = if self.get_param(first)? == self.get_param(second)? {
+                    T::one()
+                } else {
+                    T::zero()
+                };
+                self.set_param(dest, value)?;
+                self.pc += 4;
+                Executed::Running
+            }
+            Instruction::RelativeBaseOffset(param) => {
+                let offset = self.get_param(param)?.to_isize();
+                self.rbo = offset
+                    .and_then(|offset| self.rbo.checked_add(offset))
+                    .ok_or_else(|| self.overflow())?;
+                let rbo = self.rbo;
+                if let Some(entry) = self.trace_entry() {
+                    entry.rbo = Some(rbo);
+                }
+                self.pc += 2;
+                Executed::Running
+            }
+            Instruction::Break => Executed::Completed,
+            Instruction::Extended { opcode, mut params, .. } => {
+                let extension = self.mmu.extensions.get(opcode).cloned().unwrap();
+                let next = self.pc + params.len() + 1;
+                let dest = if extension.stores() { params.pop() } else { None };
+                let args = params.into_iter().map(|param| self.get_param(param)).collect::<Result<
+                    Vec<_>,
+                    _,
+                >>(
+                )?;
+                let failed = || CpuError::ExtensionFailed { pc: self.pc, word: self.word() };
+                match extension.execute(&args).ok_or_else(failed)? {
+                    Effect::Next => {
+                        self.pc = next;
+                        Executed::Running
+                    }
+                    Effect::Store(value) => {
+                        self.set_param(dest.ok_or_else(failed)?, value)?;
+                        self.pc = next;
+                        Executed::Running
+                    }
+                    Effect::Jump(target) => {
+                        self.pc = self.get_target(Parameter::Immediate(target))?;
+                        Executed::Running
+                    }
+                    Effect::Output(value) => {
+                        if let Some(entry) = self.trace_entry() {
+                            entry.output = Some(value.clone());
+                        }
+                        if let Some(history) = &mut self.history {
+                            history.record_output(value.clone());
+                        }
+                        self.pc = next;
+                        Executed::Output(value)
+                    }
+                    Effect::Exit(code) => {
+                        self.exit_code = Some(code);
+                        Executed::Completed
+                    }
+                }
+            }
+        };
+
+        if let Executed::Output(_) | Executed::Input(_) = executed {
+            self.reset_io();
+        }
+        Ok(executed)
+    }
+
+    fn word(&self) -> T {
+        self.mmu.get(self.pc)
+    }
+
+    fn overflow(&self) -> CpuError<T> {
+        CpuError::Overflow { pc: self.pc, word: self.word() }
+    }
+
+    fn get_address(&self, param: Parameter<T>) -> Result<usize, CpuError<T>> {
+        let address = match param {
+            Parameter::Immediate(_) => {
+                return Err(CpuError::WriteToImmediate { pc: self.pc, word: self.word() });
+            }
+            Parameter::Indexed(index) => index,
+            Parameter::Relative(offset) => {
+                let address = self.rbo.checked_add(offset).ok_or_else(|| self.overflow())?;
+                if address < 0 {
+                    let word = self.word();
+                    return Err(CpuError::NegativeAddress { pc: self.pc, word, address });
+                }
+                address as usize
+            }
+        };
+        match self.mmu.limit() {
+            Some(limit) if address >= limit => {
+                Err(CpuError::AddressOutOfBounds { pc: self.pc, word: self.word(), address })
+            }
+            _ => Ok(address),
+        }
+    }
+
+    fn get_target(&mut self, param: Parameter<T>) -> Result<usize, CpuError<T>> {
+        let target = self.get_param(param)?;
+        match (target.to_usize(), self.mmu.limit()) {
+            (Some(address), Some(limit)) if address >= limit => {
+                Err(CpuError::JumpOutOfRange { pc: self.pc, word: self.word(), target })
+            }
+            (Some(address), _) => Ok(address),
+            (None, _) => Err(CpuError::JumpOutOfRange { pc: self.pc, word: self.word(), target }),
+        }
+    }
+
+    fn get_param(&mut self, param: Parameter<T>) -> Result<T, CpuError<T>> {
+        let value = match param {
+            Parameter::Immediate(value) => value,
+            param => self.mmu.read(self.get_address(param)?, self.pc),
+        };
+        if let Some(entry) = self.trace_entry() {
+            entry.reads.push(value.clone());
+        }
+        Ok(value)
+    }
+
+    fn set_param(&mut self, dest: Parameter<T>, value: T) -> Result<(), CpuError<T>> {
+        let address = self.get_address(dest)?;
+        if let Some(entry) = self.trace_entry() {
+            entry.write = Some((address, value.clone()));
+        }
+        if let Some(history) = &mut self.history {
+            history.record_write(address, self.mmu.get(address));
+        }
+        match self.mmu.write(address, value, self.pc) {
+            Some(()) => Ok(()),
+            None => Err(CpuError::AddressOutOfBounds { pc: self.pc, word: self.word(), address }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &str) -> Vec<Result<i64, CpuError<i64>>> {
+        CPU::from_source(input).try_outputs_with(|| 7).collect()
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        assert_eq!(run("104,1,42"), vec![Ok(1), Err(CpuError::InvalidOpcode { pc: 2, word: 42 })]);
+        assert_eq!(run("-1"), vec![Err(CpuError::InvalidOpcode { pc: 0, word: -1 })]);
+        assert_eq!(run("304,1,99"), vec![Err(CpuError::InvalidMode { pc: 0, word: 304, mode: 3 })]);
+        assert_eq!(run("1101,1,2,3,99"), vec![]);
+        assert_eq!(
+            run("11101,1,2,3,99"),
+            vec![Err(CpuError::WriteToImmediate { pc: 0, word: 11101 })]
+        );
+        assert_eq!(run("103,0,99"), vec![Err(CpuError::WriteToImmediate { pc: 0, word: 103 })]);
+        assert_eq!(
+            run("4,-3,99"),
+            vec![Err(CpuError::NegativeAddress { pc: 0, word: 4, address: -3 })]
+        );
+        assert_eq!(
+            run("109,-5,204,1,99"),
+            vec![Err(CpuError::NegativeAddress { pc: 2, word: 204, address: -4 })]
+        );
+        assert_eq!(
+            run("1105,1,-9"),
+            vec![Err(CpuError::JumpOutOfRange { pc: 0, word: 1105, target: -9 })]
+        );
+    }
+
+    #[test]
+    fn errors_stop_execution() {
+        let mut cpu = CPU::<i64>::from_source("3,4,104,1,0");
+        assert_eq!(cpu.try_outputs_with(|| 99).collect::<Vec<_>>(), vec![Ok(1)]);
+
+        let mut cpu = CPU::<i64>::from_source("3,4,104,1,0");
+        let mut outputs = cpu.try_outputs_with(|| 42);
+        assert_eq!(outputs.next(), Some(Ok(1)));
+        assert_eq!(outputs.next(), Some(Err(CpuError::InvalidOpcode { pc: 4, word: 42 })));
+        assert_eq!(outputs.next(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid opcode in 42 at 0")]
+    fn invalid_opcode_panics() {
+        CPU::<i64>::from_source("42").outputs().last();
+    }
+}
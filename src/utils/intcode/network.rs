@@ -0,0 +1,347 @@
+use std::error::Error;
+use std::fmt;
+
+use super::memory::{DenseMemory, Memory};
+use super::resume::Status;
+use super::{CpuError, Word, CPU};
+
+pub const NAT_ADDRESS: usize = 255;
+
+// Instructions a machine may run in one turn without touching its input
+const QUANTUM: usize = 1000;
+
+// Consecutive empty reads after which a machine counts as idle
+const IDLE_READS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet<T> {
+    pub address: usize,
+    pub x: T,
+    pub y: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkError<T> {
+    Cpu { node: usize, error: CpuError<T> },
+    UnknownAddress { node: usize, address: T },
+}
+
+impl<T: fmt::Display> fmt::Display for NetworkError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cpu { node, error } => write!(f, "{} on node {}", error, node),
+            Self::UnknownAddress { node, address } => {
+                write!(f, "Node {} sent a packet to unknown address {}", node, address)
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug + fmt::Display> Error for NetworkError<T> {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action<T> {
+    Continue,
+    Send(Packet<T>),
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    ByNat,
+    Halted,
+    // Every machine is waiting on input and the NAT had nothing to send
+    Idle,
+}
+
+pub trait Nat<T> {
+    fn receive(&mut self, packet: Packet<T>) -> Action<T>;
+
+    fn idle(&mut self) -> Action<T>;
+}
+
+// Stops the network as soon as the first packet reaches the NAT
+#[derive(Debug, Clone, Default)]
+pub struct FirstPacket<T> {
+    pub packet: Option<Packet<T>>,
+}
+
+impl<T> Nat<T> for FirstPacket<T> {
+    fn receive(&mut self, packet: Packet<T>) -> Action<T> {
+        self.packet = Some(packet);
+        Action::Stop
+    }
+
+    fn idle(&mut self) -> Action<T> {
+        Action::Continue
+    }
+}
+
+// Wakes node 0 with the last packet it received whenever the network goes idle, and stops once
+// it delivers the same Y value twice in a row
+#[derive(Debug, Clone, Default)]
+pub struct Watchdog<T> {
+    pub last: Option<Packet<T>>,
+    pub delivered: Option<T>,
+}
+
+impl<T: Clone + PartialEq> Nat<T> for Watchdog<T> {
+    fn receive(&mut self, packet: Packet<T>) -> Action<T> {
+        self.last = Some(packet);
+        Action::Continue
+    }
+
+    fn idle(&mut self) -> Action<T> {
+        let packet = match &self.last {
+            Some(packet) => Packet { address: 0, ..packet.clone() },
+            None => return Action::Continue,
+        };
+        if self.delivered.as_ref() == Some(&packet.y) {
+            return Action::Stop;
+        }
+        self.delivered = Some(packet.y.clone());
+        Action::Send(packet)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node<T, M> {
+    cpu: CPU<T, M>,
+    outgoing: Vec<T>,
+    empty_reads: usize,
+    halted: bool,
+}
+
+impl<T, M> Node<T, M> {
+    fn is_idle(&self) -> bool {
+        self.halted || (self.cpu.pending_inputs() == 0 && self.empty_reads >= IDLE_READS)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Network<T, M = DenseMemory<T>> {
+    nodes: Vec<Node<T, M>>,
+    nat_address: usize,
+}
+
+impl<T: Word, M: Memory<T> + Clone> Network<T, M> {
+    // Every node boots a copy of the same program and receives its address as the first input
+    pub fn new(cpu: &CPU<T, M>, size: usize) -> Network<T, M> {
+        Network::from_cpus((0..size).map(|_| cpu.clone()).collect())
+    }
+}
+
+impl<T: Word, M: Memory<T>> Network<T, M> {
+    pub fn from_cpus(cpus: Vec<CPU<T, M>>) -> Network<T, M> {
+        let nodes = cpus
+            .into_iter()
+            .enumerate()
+            .map(|(address, mut cpu)| {
+                cpu.provide_input(T::from_usize(address).unwrap());
+                Node { cpu, outgoing: Vec::new(), empty_reads: 0, halted: false }
+            })
+            .collect();
+        Network { nodes, nat_address: NAT_ADDRESS }
+    }
+
+    pub fn set_nat_address(&mut self, address: usize) {
+        self.nat_address = address;
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.nodes.iter().all(Node::is_idle)
+    }
+
+    pub fn cpu(&self, address: usize) -> &CPU<T, M> {
+        &self.nodes[address].cpu
+    }
+
+    pub fn send(&mut self, packet: Packet<T>) {
+        let node = &mut self.nodes[packet.address];
+        node.cpu.provide_inputs(vec![packet.x, packet.y]);
+        node.empty_reads = 0;
+    }
+
+    // Gives every machine one turn and returns the packets addressed to the NAT, in order
+    pub fn step(&mut self) -> Result<Vec<Packet<T>>, NetworkError<T>> {
+        let mut packets = Vec::new();
+        for address in 0..self.nodes.len() {
+            for packet in self.turn(address)? {
+                if packet.address == self.nat_address {
+                    packets.push(packet);
+                } else {
+                    self.send(packet);
+                }
+            }
+        }
+        Ok(packets)
+    }
+
+    // Runs until the NAT stops the network, every machine has halted, or the network goes idle
+    // without the NAT waking it
+    pub fn run<N: Nat<T>>(&mut self, nat: &mut N) -> Result<Stopped, NetworkError<T>> {
+        loop {
+            for packet in self.step()? {
+                match nat.receive(packet) {
+                    Action::Continue => {}
+                    Action::Send(packet) => self.send(packet),
+                    Action::Stop => return Ok(Stopped::ByNat),
+                }
+            }
+            if self.nodes.iter().all(|node| node.halted) {
+                return Ok(Stopped::Halted);
+            }
+            if self.is_idle() {
+                match nat.idle() {
+                    Action::Continue => return Ok(Stopped::Idle),
+                    Action::Send(packet) => self.send(packet),
+                    Action::Stop => return Ok(Stopped::ByNat),
+                }
+            }
+        }
+    }
+
+    // A turn ends after the machine reads one input, halts, or runs out of its quantum
+    fn turn(&mut self, address: usize) -> Result<Vec<Packet<T>>, NetworkError<T>> {
+        let (size, nat_address) = (self.nodes.len(), self.nat_address);
+        let node = &mut self.nodes[address];
+        let mut packets = Vec::new();
+        if node.halted {
+            return Ok(packets);
+        }
+
+        let error = |error| NetworkError::Cpu { node: address, error };
+        for _ in 0..QUANTUM {
+            let pending = node.cpu.pending_inputs();
+            match node.cpu.try_step().map_err(error)? {
+                // Reads never block, they get -1 when no packet is waiting
+                Some(Status::NeedsInput) => {
+                    node.empty_reads += 1;
+                    node.cpu.provide_input(-T::one());
+                    node.cpu.try_step().map_err(error)?;
+                    break;
+                }
+                None if node.cpu.pending_inputs() < pending => {
+                    node.empty_reads = 0;
+                    break;
+                }
+                None => {}
+                Some(Status::Output(value)) => {
+                    node.outgoing.push(value);
+                    node.empty_reads = 0;
+                    if node.outgoing.len() == 3 {
+                        let y = node.outgoing.pop().unwrap();
+                        let x = node.outgoing.pop().unwrap();
+                        let target = node.outgoing.pop().unwrap();
+                        let destination = target.to_usize().filter(|&destination| {
+                            destination < size || destination == nat_address
+                        });
+                        match destination {
+                            Some(destination) => {
+                                packets.push(Packet { address: destination, x, y })
+                            }
+                            None => {
+                                return Err(NetworkError::UnknownAddress {
+                                    node: address,
+                                    address: target,
+                                })
+                            }
+                        }
+                    }
+                }
+                Some(Status::Halted) => {
+                    node.halted = true;
+                    break;
+                }
+            }
+        }
+        Ok(packets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::asm::assemble;
+
+    // Node 0 starts a packet around the ring, and every node passes it on to the next address
+    // with its Y value incremented up to 4
+    const RING: &str = "
+                RED IN(address)
+                ADD IN(address), IM(1), IN(next)
+                JIT IN(address), IM(poll)
+                WRT IM(1)
+                WRT IM(7)
+                WRT IM(0)
+        poll:   RED IN(x)
+                EQL IN(x), IM(-1), IN(t)
+                JIT IN(t), IM(poll)
+                RED IN(y)
+                LST IN(y), IM(4), IN(t)
+                ADD IN(y), IN(t), IN(y)
+                WRT IN(next)
+                WRT IN(x)
+                WRT IN(y)
+                JIT IM(1), IM(poll)
+        address: DAT 0
+        next:   DAT 0
+        x:      DAT 0
+        y:      DAT 0
+        t:      DAT 0
+    ";
+
+    fn ring() -> Network<i64> {
+        let cpu = CPU::new(assemble(RING).unwrap());
+        let mut network = Network::new(&cpu, 3);
+        network.set_nat_address(3);
+        network
+    }
+
+    #[test]
+    fn routes_packets_to_the_nat() {
+        let mut network = ring();
+        let mut nat = FirstPacket::default();
+        assert_eq!(network.run(&mut nat), Ok(Stopped::ByNat));
+        assert_eq!(nat.packet, Some(Packet { address: 3, x: 7, y: 2 }));
+    }
+
+    #[test]
+    fn nat_wakes_idle_network() {
+        let mut network = ring();
+        let mut nat = Watchdog::default();
+        assert_eq!(network.run(&mut nat), Ok(Stopped::ByNat));
+        assert_eq!(nat.delivered, Some(4));
+        assert!(network.is_idle());
+    }
+
+    #[test]
+    fn idle_networks_stop() {
+        // Polls for input forever without sending anything
+        let cpu = CPU::<i64>::from_source("3,100,3,100,1105,1,2");
+        let mut network = Network::new(&cpu, 2);
+        assert_eq!(network.run(&mut FirstPacket::default()), Ok(Stopped::Idle));
+
+        let mut network = Network::new(&cpu, 2);
+        let mut nat = Watchdog::default();
+        assert_eq!(network.run(&mut nat), Ok(Stopped::Idle));
+        assert_eq!(nat.delivered, None);
+
+        let cpu = CPU::<i64>::from_source("3,100,99");
+        let mut network = Network::new(&cpu, 2);
+        assert_eq!(network.run(&mut FirstPacket::default()), Ok(Stopped::Halted));
+    }
+
+    #[test]
+    fn unknown_addresses_are_reported() {
+        let cpu = CPU::new(assemble("RED IN(0)\nWRT IM(-5)\nWRT IM(1)\nWRT IM(2)\nBRK").unwrap());
+        let mut network = Network::<i64>::new(&cpu, 2);
+        assert_eq!(
+            network.run(&mut FirstPacket::default()),
+            Err(NetworkError::UnknownAddress { node: 0, address: -5 })
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::memory::Memory;
+use super::{Instruction, Word, CPU};
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    hits: BTreeMap<usize, usize>,
+    opcodes: HashMap<&'static str, usize>,
+    // Addresses reached other than by falling through from the previous instruction
+    leaders: BTreeSet<usize>,
+    next: Option<usize>,
+    io_intervals: Vec<Duration>,
+    last_io: Option<Instant>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockLine {
+    pub address: usize,
+    pub hits: usize,
+    pub code: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub entries: usize,
+    pub executed: usize,
+    pub lines: Vec<BlockLine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileReport {
+    pub executed: usize,
+    pub opcodes: Vec<(&'static str, usize)>,
+    pub blocks: Vec<Block>,
+    pub io_intervals: Vec<Duration>,
+}
+
+fn ends_block<T>(instruction: &Instruction<T>) -> bool {
+    matches!(
+        instruction,
+        Instruction::JumpIfTrue(..)
+            | Instruction::JumpIfFalse(..)
+            | Instruction::Break
+            | Instruction::Extended { .. }
+    )
+}
+
+impl Profile {
+    pub(super) fn record<T: Word>(&mut self, pc: usize, instruction: &Instruction<T>) {
+        *self.hits.entry(pc).or_insert(0) += 1;
+        *self.opcodes.entry(instruction.mnemonic()).or_insert(0) += 1;
+
+        if self.next != Some(pc) {
+            self.leaders.insert(pc);
+        }
+        self.next = if ends_block(instruction) { None } else { Some(pc + instruction.len()) };
+
+        if let Instruction::Read(_) | Instruction::Write(_) = instruction {
+            let now = Instant::now();
+            if let Some(last) = self.last_io {
+                self.io_intervals.push(now - last);
+            }
+            self.last_io = Some(now);
+        }
+    }
+
+    pub fn hits(&self, address: usize) -> usize {
+        self.hits.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn executed(&self) -> usize {
+        self.hits.values().sum()
+    }
+}
+
+impl<T, M> CPU<T, M> {
+    pub fn enable_profiling(&mut self) {
+        if self.profile.is_none() {
+            self.profile = Some(Box::default());
+        }
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take().map(|profile| *profile)
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_deref()
+    }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    // Blocks are rebuilt from the current memory, so self-modified code shows its latest form
+    pub fn profile_report(&self, top: usize) -> Option<ProfileReport> {
+        let profile = self.profile.as_ref()?;
+
+        let mut opcodes: Vec<_> =
+            profile.opcodes.iter().map(|(&name, &count)| (name, count)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        let mut blocks: Vec<_> = profile
+            .leaders
+            .iter()
+            .map(|&start| {
+                let mut lines = Vec::new();
+                let mut address = start;
+                loop {
+                    let instruction = match Instruction::decode(&self.mmu, address) {
+                        Ok(instruction) => instruction,
+                        Err(_) => break,
+                    };
+                    let code = instruction.to_string();
+                    lines.push(BlockLine { address, hits: profile.hits(address), code });
+
+                    address += instruction.len();
+                    if ends_block(&instruction)
+                        || profile.leaders.contains(&address)
+                        || profile.hits(address) == 0
+                    {
+                        break;
+                    }
+                }
+                let executed = lines.iter().map(|line| line.hits).sum();
+                Block { start, entries: profile.hits(start), executed, lines }
+            })
+            .collect();
+        blocks.sort_by(|a, b| b.executed.cmp(&a.executed).then(a.start.cmp(&b.start)));
+        blocks.truncate(top);
+
+        Some(ProfileReport {
+            executed: profile.executed(),
+            opcodes,
+            blocks,
+            io_intervals: profile.io_intervals.clone(),
+        })
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} instructions executed", self.executed)?;
+        if !self.io_intervals.is_empty() {
+            let total: Duration = self.io_intervals.iter().sum();
+            let longest = self.io_intervals.iter().max().unwrap();
+            writeln!(
+                f,
+                "{} I/O intervals, mean {:?}, longest {:?}",
+                self.io_intervals.len(),
+                total / self.io_intervals.len() as u32,
+                longest
+            )?;
+        }
+
+        writeln!(f, "\nOpcodes:")?;
+        for (name, count) in &self.opcodes {
+            let share = 100.0 * *count as f64 / self.executed as f64;
+            writeln!(f, "  {} {:>12} {:>6.2}%", name, count, share)?;
+        }
+
+        for block in &self.blocks {
+            writeln!(
+                f,
+                "\nBlock at {}: {} entries, {} instructions",
+                block.start, block.entries, block.executed
+            )?;
+            for line in &block.lines {
+                writeln!(f, "  {:>12} {:>5}: {}", line.hits, line.address, line.code)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::memory::SparseMemory;
+    use crate::utils::intcode::parse_program;
+    use crate::utils::intcode::resume::Status;
+
+    #[test]
+    fn counts_hot_spots() {
+        // Counts down from 3, outputting every value
+        let mut cpu = CPU::<i64>::from_source("4,11,1001,11,-1,11,1005,11,0,99,0,3");
+        cpu.enable_profiling();
+        assert_eq!(cpu.outputs().collect::<Vec<_>>(), vec![3, 2, 1]);
+
+        let profile = cpu.profile().unwrap();
+        assert_eq!((profile.hits(0), profile.hits(6), profile.hits(9)), (3, 3, 1));
+        assert_eq!(profile.executed(), 10);
+
+        let report = cpu.profile_report(5).unwrap();
+        assert_eq!(report.opcodes, vec![("ADD", 3), ("JIT", 3), ("WRT", 3), ("BRK", 1)]);
+        assert_eq!(report.io_intervals.len(), 2);
+        assert_eq!(report.blocks.len(), 2);
+
+        let hottest = &report.blocks[0];
+        assert_eq!((hottest.start, hottest.entries, hottest.executed), (0, 3, 9));
+        assert_eq!(
+            hottest.lines.iter().map(|line| &line.code[..]).collect::<Vec<_>>(),
+            vec!["WRT IN(11)", "ADD IN(11), IM(-1), IN(11)", "JIT IN(11), IM(0)"]
+        );
+        assert!(report.to_string().contains("           3     6: JIT IN(11), IM(0)"));
+    }
+
+    #[test]
+    fn far_jumps_stay_cheap() {
+        // Stores a BRK 2^40 words away and jumps to it
+        let far = "109,1099511627776,21101,99,0,0,1106,0,1099511627776";
+        let mut cpu = CPU::with_memory(SparseMemory::from(parse_program::<i64>(far)));
+        cpu.enable_profiling();
+        assert_eq!(cpu.try_run(), Ok(Status::Halted));
+
+        let profile = cpu.profile().unwrap();
+        assert_eq!((profile.hits(6), profile.hits(1 << 40)), (1, 1));
+        assert_eq!(profile.executed(), 4);
+    }
+
+    // cargo test --release profile_puzzles -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn profile_puzzles() {
+        let mut cpu = CPU::<i64>::from_source(include_str!("../../day09/input"));
+        cpu.enable_profiling();
+        assert_eq!(cpu.outputs_with(|| 2).last(), Some(87571));
+        println!("Day 9:\n{}", cpu.profile_report(5).unwrap());
+
+        let mut cpu = CPU::<i64>::from_source(include_str!("../../day13/input"));
+        cpu.enable_profiling();
+        assert_eq!(cpu.outputs().count() % 3, 0);
+        println!("Day 13:\n{}", cpu.profile_report(5).unwrap());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+
+use super::memory::Memory;
+use super::{CpuError, ExecutionResult, Word, CPU};
+
+// Where a machine stopped. Nothing borrows the CPU, so it can be stored and resumed later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status<T> {
+    NeedsInput,
+    Output(T),
+    Halted,
+}
+
+#[derive(Debug, Clone)]
+pub struct Queues<T> {
+    inputs: VecDeque<T>,
+    outputs: VecDeque<T>,
+}
+
+impl<T> Default for Queues<T> {
+    fn default() -> Queues<T> {
+        Queues { inputs: VecDeque::new(), outputs: VecDeque::new() }
+    }
+}
+
+impl<T, M> CPU<T, M> {
+    pub fn provide_input(&mut self, value: T) {
+        self.queues.inputs.push_back(value);
+    }
+
+    pub fn provide_inputs(&mut self, values: impl IntoIterator<Item = T>) {
+        self.queues.inputs.extend(values);
+    }
+
+    pub fn pending_inputs(&self) -> usize {
+        self.queues.inputs.len()
+    }
+
+    pub fn take_outputs(&mut self) -> Vec<T> {
+        self.queues.outputs.drain(..).collect()
+    }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    pub fn run(&mut self) -> Status<T> {
+        self.try_run().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // Executes at most one instruction, returning a status if the machine stopped on it. A read
+    // finding the input queue empty isn't fetched, so hooks like the budget and the profile only
+    // see it once it actually executes.
+    pub fn try_step(&mut self) -> Result<Option<Status<T>>, CpuError<T>> {
+        let reads = self.mmu.get(self.pc).to_usize().map(|word| word % 100) == Some(3);
+        if reads && self.queues.inputs.is_empty() {
+            return Ok(Some(Status::NeedsInput));
+        }
+
+        let instruction = self.next_instruction()?;
+        let mut input = if reads { self.queues.inputs.pop_front() } else { None };
+        let error = match self.try_execute_instruction(instruction) {
+            Ok(ExecutionResult::YieldedInput(sink)) => {
+                sink(input.take().unwrap());
+                return Ok(None);
+            }
+            Ok(ExecutionResult::YieldedOutput(value)) => return Ok(Some(Status::Output(value))),
+            Ok(ExecutionResult::Completed) => return Ok(Some(Status::Halted)),
+            Ok(ExecutionResult::Running) => return Ok(None),
+            Err(err) => err,
+        };
+        // Leaves the input at the head of the queue for whoever fixes the machine up
+        if let Some(value) = input {
+            self.queues.inputs.push_front(value);
+        }
+        Err(error)
+    }
+
+    // Runs until the next output, or until a read finds the input queue empty
+    pub fn try_run(&mut self) -> Result<Status<T>, CpuError<T>> {
+        loop {
+            if let Some(status) = self.try_step()? {
+                return Ok(status);
+            }
+        }
+    }
+
+    // Like try_run, but queues outputs instead of stopping on them
+    pub fn run_until_blocked(&mut self) -> Result<Status<T>, CpuError<T>> {
+        loop {
+            match self.try_run()? {
+                Status::Output(value) => self.queues.outputs.push_back(value),
+                status => return Ok(status),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::topology::Topology;
+
+    // Doubles every input until it reads a zero
+    const DOUBLER: &str = "3,15,1006,15,14,102,2,15,15,4,15,1105,1,0,99,0";
+
+    #[test]
+    fn parks_and_resumes() {
+        let mut cpu = CPU::<i64>::from_source(DOUBLER);
+        assert_eq!(cpu.run(), Status::NeedsInput);
+        assert_eq!(cpu.run(), Status::NeedsInput);
+
+        cpu.provide_inputs(vec![3, 5]);
+        assert_eq!(cpu.run(), Status::Output(6));
+        let mut parked = vec![cpu];
+        let mut cpu = parked.pop().unwrap();
+        assert_eq!(cpu.run(), Status::Output(10));
+        assert_eq!(cpu.run(), Status::NeedsInput);
+
+        cpu.provide_inputs(vec![1, 2, 0, 7]);
+        assert_eq!(cpu.run_until_blocked(), Ok(Status::Halted));
+        assert_eq!(cpu.take_outputs(), vec![2, 4]);
+        assert_eq!(cpu.pending_inputs(), 1);
+        assert_eq!(cpu.run(), Status::Halted);
+    }
+
+    #[test]
+    fn blocked_reads_execute_once() {
+        let mut cpu = CPU::<i64>::from_source(DOUBLER);
+        cpu.enable_profiling();
+        cpu.enable_history(None);
+        for _ in 0..3 {
+            assert_eq!(cpu.run(), Status::NeedsInput);
+        }
+        cpu.provide_input(0);
+        assert_eq!(cpu.run(), Status::Halted);
+        assert_eq!(cpu.history().unwrap().executed(), 3);
+        assert_eq!(cpu.profile().unwrap().hits(0), 1);
+
+        // Inputs aren't lost to a read that fails, and keep their order
+        let mut cpu = CPU::<i64>::from_source("103,0,99");
+        cpu.provide_inputs(vec![1, 2, 3]);
+        assert!(cpu.try_run().is_err());
+        assert_eq!(cpu.pending_inputs(), 3);
+        *cpu.mmu.get_mut(0) = 3;
+        assert_eq!(cpu.run(), Status::Halted);
+        assert_eq!((cpu.mmu.get(0), cpu.pending_inputs()), (1, 2));
+    }
+
+    #[test]
+    fn runs_an_amplifier_ring_by_hand() {
+        let cpu = CPU::<i64>::from_source(include_str!("../../day07/input"));
+        let phases = [9, 8, 7, 6, 5];
+        let mut amplifiers: Vec<_> = phases
+            .iter()
+            .map(|&phase| {
+                let mut amplifier = cpu.clone();
+                amplifier.provide_input(phase);
+                amplifier
+            })
+            .collect();
+
+        let mut signal = 0;
+        'ring: loop {
+            for amplifier in &mut amplifiers {
+                amplifier.provide_input(signal);
+                match amplifier.run() {
+                    Status::Output(value) => signal = value,
+                    Status::Halted => break 'ring,
+                    Status::NeedsInput => panic!("Amplifier didn't produce a signal"),
+                }
+            }
+        }
+
+        let mut ring = Topology::ring(&cpu, &phases);
+        ring.send(0, 0);
+        let report = ring.run().unwrap();
+        assert_eq!(report.outputs[4].last(), Some(&signal));
+    }
+}
@@ -0,0 +1,293 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use super::memory::{DenseMemory, Memory};
+use super::{Word, CPU, MMU};
+
+const HEADER: &str = "intcode-snapshot 1";
+
+#[derive(Debug, Clone)]
+pub struct Snapshot<T, M = DenseMemory<T>> {
+    pc: usize,
+    rbo: isize,
+    mmu: MMU<T, M>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    InvalidHeader(String),
+    InvalidLine { line: usize, text: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Failed reading snapshot: {}", err),
+            Self::InvalidHeader(header) => write!(f, "Unsupported snapshot header {}", header),
+            Self::InvalidLine { line, text } => write!(f, "Invalid line {}: {}", line, text),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl<T: Word, M: Memory<T>> Snapshot<T, M> {
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn rbo(&self) -> isize {
+        self.rbo
+    }
+
+    pub fn memory(&self) -> Vec<T> {
+        self.mmu.to_vec()
+    }
+
+    pub fn to_cpu(&self) -> CPU<T, M> {
+        let mut cpu = CPU::with_memory(self.mmu.memory.clone());
+        cpu.pc = self.pc;
+        cpu.rbo = self.rbo;
+        cpu
+    }
+}
+
+impl<T> Snapshot<T> {
+    pub fn shared_pages(&self, other: &Snapshot<T>) -> usize {
+        self.mmu.memory.shared_pages(&other.mmu.memory)
+    }
+}
+
+impl<T: Word, M: Memory<T>> Snapshot<T, M> {
+    pub fn write_to(&self, mut out: impl Write) -> io::Result<()> {
+        // Runs of zeros are written as 0*N, so memory touched far away stays small on disk
+        fn flush(memory: &mut Vec<String>, zeros: &mut usize) {
+            match *zeros {
+                0 => {}
+                1 => memory.push("0".to_string()),
+                run => memory.push(format!("0*{}", run)),
+            }
+            *zeros = 0;
+        }
+
+        let (mut memory, mut zeros, mut next) = (Vec::new(), 0, 0);
+        for (index, value) in self.mmu.cells() {
+            zeros += index - next;
+            next = index + 1;
+            if value.is_zero() {
+                zeros += 1;
+            } else {
+                flush(&mut memory, &mut zeros);
+                memory.push(value.to_string());
+            }
+        }
+        zeros += self.mmu.len().saturating_sub(next);
+        flush(&mut memory, &mut zeros);
+
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "pc {}", self.pc)?;
+        writeln!(out, "rbo {}", self.rbo)?;
+        writeln!(out, "memory {}", memory.join(","))
+    }
+}
+
+impl<T: Word + FromStr, M: Memory<T> + From<Vec<T>>> Snapshot<T, M> {
+    pub fn read_from(input: impl BufRead) -> Result<Snapshot<T, M>, SnapshotError> {
+        let mut lines = input.lines();
+
+        let header = lines.next().transpose()?.unwrap_or_default();
+        if header.trim() != HEADER {
+            return Err(SnapshotError::InvalidHeader(header));
+        }
+
+        let mut field = |line: usize, name: &str| -> Result<String, SnapshotError> {
+            let text = lines.next().transpose()?.unwrap_or_default();
+            match text.strip_prefix(name).and_then(|rest| rest.strip_prefix(' ')) {
+                Some(value) => Ok(value.trim().to_string()),
+                None if text == name => Ok(String::new()),
+                None => Err(SnapshotError::InvalidLine { line, text }),
+            }
+        };
+
+        let invalid =
+            |line: usize, text: &str| SnapshotError::InvalidLine { line, text: text.to_string() };
+
+        let pc = field(2, "pc")?;
+        let pc = pc.parse().map_err(|_| invalid(2, &pc))?;
+        let rbo = field(3, "rbo")?;
+        let rbo = rbo.parse().map_err(|_| invalid(3, &rbo))?;
+        let text = field(4, "memory")?;
+        let mut memory = M::from(Vec::new());
+        let mut len = 0;
+        for word in text.split(',').filter(|_| !text.is_empty()) {
+            if let Some(run) = word.strip_prefix("0*") {
+                len += run.parse::<usize>().map_err(|_| invalid(4, &text))?;
+                continue;
+            }
+            let value: T = word.parse().map_err(|_| invalid(4, &text))?;
+            if !value.is_zero() {
+                *memory.get_mut(len).ok_or_else(|| invalid(4, &text))? = value;
+            }
+            len += 1;
+        }
+        if memory.len() < len {
+            *memory.get_mut(len - 1).ok_or_else(|| invalid(4, &text))? = T::zero();
+        }
+
+        Ok(Snapshot { pc, rbo, mmu: MMU::new(memory) })
+    }
+}
+
+impl<T, M: Clone> CPU<T, M> {
+    pub fn snapshot(&self) -> Snapshot<T, M> {
+        let mmu = MMU::new(self.mmu.memory.clone());
+        Snapshot { pc: self.pc, rbo: self.rbo, mmu }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot<T, M>) {
+        self.pc = snapshot.pc;
+        self.rbo = snapshot.rbo;
+        self.mmu.memory = snapshot.mmu.memory.clone();
+        self.exit_code = None;
+        if let Some(cache) = &mut self.mmu.cache {
+            cache.clear();
+        }
+        // Undoing an instruction from before the restore would write into the restored memory
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        self.forget_states();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::budget::Budget;
+    use crate::utils::intcode::memory::SparseMemory;
+    use crate::utils::intcode::parse_program;
+    use crate::utils::intcode::resume::Status;
+
+    const INPUT: &str = include_str!("../../day15/input");
+
+    #[test]
+    fn snapshots_share_unchanged_pages() {
+        let mut cpu = CPU::<i64>::from_source(INPUT);
+        let before = cpu.snapshot();
+        let pages = before.shared_pages(&before);
+
+        assert_eq!(cpu.outputs_with(|| 1).next(), Some(0));
+        let after = cpu.snapshot();
+
+        let shared = after.shared_pages(&before);
+        assert!(shared > 0 && shared < pages, "{} of {} pages shared", shared, pages);
+        assert_eq!(before.memory(), CPU::<i64>::from_source(INPUT).snapshot().memory());
+    }
+
+    #[test]
+    fn restoring_resumes_execution() {
+        let mut cpu = CPU::<i64>::from_source(INPUT);
+        cpu.outputs_with(|| 4).next();
+        let checkpoint = cpu.snapshot();
+
+        let first: Vec<_> =
+            [1, 1, 3, 4].iter().map(|&m| cpu.outputs_with(move || m).next()).collect();
+        cpu.restore(&checkpoint);
+        let second: Vec<_> =
+            [1, 1, 3, 4].iter().map(|&m| cpu.outputs_with(move || m).next()).collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn restoring_resets_loop_detection() {
+        let mut cpu = CPU::<i64>::from_source("1001,9,-1,9,1005,9,0,99,0,3");
+        cpu.set_budget(Budget { detect_loops: true, ..Budget::default() });
+        let checkpoint = cpu.snapshot();
+        assert_eq!((cpu.try_step(), cpu.try_step()), (Ok(None), Ok(None)));
+
+        // States seen before restoring would otherwise look like the countdown repeating
+        cpu.restore(&checkpoint);
+        assert_eq!(cpu.try_run(), Ok(Status::Halted));
+        assert_eq!(cpu.mmu.get(9), 0);
+    }
+
+    #[test]
+    fn restoring_forgets_history() {
+        let mut cpu = CPU::<i64>::from_source("1101,1,1,9,1101,2,2,10,99,0,0");
+        cpu.enable_history(Some(10));
+        let checkpoint = cpu.snapshot();
+        assert_eq!(cpu.try_run(), Ok(Status::Halted));
+        cpu.exit_code = Some(7);
+
+        // The history would otherwise step back to the instructions run before restoring
+        cpu.restore(&checkpoint);
+        assert_eq!(cpu.step_back(1), None);
+        assert_eq!((cpu.pc, cpu.exit_code), (0, None));
+
+        assert_eq!(cpu.try_run(), Ok(Status::Halted));
+        assert_eq!(cpu.history().unwrap().executed(), 3);
+        assert!(cpu.step_back(2).is_some());
+        assert_eq!((cpu.pc, cpu.mmu.get(10)), (4, 0));
+    }
+
+    #[test]
+    fn snapshots_round_trip_through_text() {
+        let mut cpu = CPU::<i64>::from_source("109,7,21101,2,3,0,99");
+        cpu.outputs().last();
+
+        let mut out = Vec::new();
+        cpu.snapshot().write_to(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "intcode-snapshot 1\npc 6\nrbo 7\nmemory 109,7,21101,2,3,0,99,5\n");
+
+        let snapshot = Snapshot::<i64>::read_from(text.as_bytes()).unwrap();
+        assert_eq!((snapshot.pc(), snapshot.rbo()), (6, 7));
+        assert_eq!(snapshot.memory(), vec![109, 7, 21101, 2, 3, 0, 99, 5]);
+        assert_eq!(snapshot.to_cpu().outputs().count(), 0);
+
+        assert!(matches!(
+            Snapshot::<i64, DenseMemory<_>>::read_from("intcode-snapshot 2\n".as_bytes()),
+            Err(SnapshotError::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            Snapshot::<i64, DenseMemory<_>>::read_from("intcode-snapshot 1\npc x\n".as_bytes()),
+            Err(SnapshotError::InvalidLine { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn far_writes_stay_small_in_snapshots() {
+        // Stores the input 2^40 words away and a zero just past it
+        let far = "109,1099511627776,203,0,21101,0,0,1,99";
+        let mut cpu = CPU::with_memory(SparseMemory::from(parse_program::<i64>(far)));
+        cpu.provide_input(42);
+        assert_eq!(cpu.try_run(), Ok(Status::Halted));
+
+        let mut out = Vec::new();
+        cpu.snapshot().write_to(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text,
+            "intcode-snapshot 1\npc 8\nrbo 1099511627776\n\
+             memory 109,1099511627776,203,0,21101,0*2,1,99,0*1099511627767,42,0\n"
+        );
+
+        let snapshot = Snapshot::<i64, SparseMemory<_>>::read_from(text.as_bytes()).unwrap();
+        let cpu = snapshot.to_cpu();
+        assert_eq!(cpu.mmu.len(), (1 << 40) + 2);
+        assert_eq!(cpu.mmu.get(1 << 40), 42);
+        assert_eq!(cpu.mmu.memory.allocated_pages(), 2);
+
+        // Nothing past the stored cells is executed, so compiling doesn't touch the gap either
+        assert_eq!(cpu.compile().outputs().count(), 0);
+    }
+}
@@ -0,0 +1,593 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+
+use super::extension::Extensions;
+use super::memory::Memory;
+use super::{CpuError, Instruction, Parameter, Word, CPU};
+
+const MAX_STEPS: usize = 100_000;
+const MAX_PATHS: usize = 1000;
+
+// A polynomial over named variables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr<T> {
+    // Coefficient of every monomial, given as a sorted list of variable names. Zero
+    // coefficients are never stored, so equal polynomials compare equal.
+    terms: BTreeMap<Vec<String>, T>,
+}
+
+impl<T: Word> Expr<T> {
+    pub fn zero() -> Expr<T> {
+        Expr { terms: BTreeMap::new() }
+    }
+
+    pub fn constant(value: T) -> Expr<T> {
+        let mut expr = Expr::zero();
+        if !value.is_zero() {
+            expr.terms.insert(Vec::new(), value);
+        }
+        expr
+    }
+
+    pub fn variable(name: &str) -> Expr<T> {
+        let mut expr = Expr::zero();
+        expr.terms.insert(vec![name.to_string()], T::one());
+        expr
+    }
+
+    pub fn as_constant(&self) -> Option<T> {
+        match self.terms.len() {
+            0 => Some(T::zero()),
+            1 => self.terms.get(&Vec::new()).cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn degree(&self) -> usize {
+        self.terms.keys().map(Vec::len).max().unwrap_or(0)
+    }
+
+    pub fn coefficient(&self, monomial: &[&str]) -> T {
+        let mut key: Vec<_> = monomial.iter().map(|name| name.to_string()).collect();
+        key.sort();
+        self.terms.get(&key).cloned().unwrap_or_else(T::zero)
+    }
+
+    pub fn variables(&self) -> BTreeSet<&str> {
+        self.terms.keys().flatten().map(String::as_str).collect()
+    }
+
+    fn add_term(&mut self, monomial: Vec<String>, coefficient: T) -> Option<()> {
+        let sum = match self.terms.remove(&monomial) {
+            Some(existing) => existing.checked_add(&coefficient)?,
+            None => coefficient,
+        };
+        if !sum.is_zero() {
+            self.terms.insert(monomial, sum);
+        }
+        Some(())
+    }
+
+    pub fn checked_add(&self, other: &Expr<T>) -> Option<Expr<T>> {
+        let mut sum = self.clone();
+        for (monomial, coefficient) in &other.terms {
+            sum.add_term(monomial.clone(), coefficient.clone())?;
+        }
+        Some(sum)
+    }
+
+    pub fn checked_mul(&self, other: &Expr<T>) -> Option<Expr<T>> {
+        let mut product = Expr::zero();
+        for (a, x) in &self.terms {
+            for (b, y) in &other.terms {
+                let mut monomial: Vec<_> = a.iter().chain(b).cloned().collect();
+                monomial.sort();
+                product.add_term(monomial, x.checked_mul(y)?)?;
+            }
+        }
+        Some(product)
+    }
+
+    pub fn checked_sub(&self, other: &Expr<T>) -> Option<Expr<T>> {
+        self.checked_add(&other.checked_mul(&Expr::constant(-T::one()))?)
+    }
+
+    // Returns None if a variable is missing from the assignment or the result overflows
+    pub fn evaluate(&self, assignment: &[(&str, T)]) -> Option<T> {
+        self.terms.iter().try_fold(T::zero(), |sum, (monomial, coefficient)| {
+            let term = monomial.iter().try_fold(coefficient.clone(), |product, name| {
+                let (_, value) = assignment.iter().find(|(variable, _)| variable == name)?;
+                product.checked_mul(value)
+            })?;
+            sum.checked_add(&term)
+        })
+    }
+}
+
+impl<T: Word> fmt::Display for Expr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+
+        let mut terms: Vec<_> = self.terms.iter().collect();
+        terms.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(b.0)));
+
+        for (index, (monomial, coefficient)) in terms.into_iter().enumerate() {
+            match (index, coefficient.is_negative()) {
+                (0, false) => {}
+                (0, true) => write!(f, "-")?,
+                (_, false) => write!(f, " + ")?,
+                (_, true) => write!(f, " - ")?,
+            }
+
+            let magnitude = coefficient.abs();
+            let mut factors = Vec::new();
+            if monomial.is_empty() || magnitude != T::one() {
+                factors.push(magnitude.to_string());
+            }
+            let mut names = monomial.iter().peekable();
+            while let Some(name) = names.next() {
+                let mut power = 1;
+                while names.next_if_eq(&name).is_some() {
+                    power += 1;
+                }
+                if power == 1 {
+                    factors.push(name.clone());
+                } else {
+                    factors.push(format!("{}^{}", name, power));
+                }
+            }
+            write!(f, "{}", factors.join("*"))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Constraint<T> {
+    Zero(Expr<T>),
+    NonZero(Expr<T>),
+    Negative(Expr<T>),
+    NonNegative(Expr<T>),
+}
+
+impl<T: Word> Constraint<T> {
+    pub fn expr(&self) -> &Expr<T> {
+        match self {
+            Self::Zero(expr) | Self::NonZero(expr) => expr,
+            Self::Negative(expr) | Self::NonNegative(expr) => expr,
+        }
+    }
+
+    pub fn negation(&self) -> Constraint<T> {
+        match self {
+            Self::Zero(expr) => Self::NonZero(expr.clone()),
+            Self::NonZero(expr) => Self::Zero(expr.clone()),
+            Self::Negative(expr) => Self::NonNegative(expr.clone()),
+            Self::NonNegative(expr) => Self::Negative(expr.clone()),
+        }
+    }
+
+    fn test(&self, value: &T) -> bool {
+        match self {
+            Self::Zero(_) => value.is_zero(),
+            Self::NonZero(_) => !value.is_zero(),
+            Self::Negative(_) => value.is_negative(),
+            Self::NonNegative(_) => !value.is_negative(),
+        }
+    }
+
+    pub fn holds(&self, assignment: &[(&str, T)]) -> Option<bool> {
+        Some(self.test(&self.expr().evaluate(assignment)?))
+    }
+}
+
+impl<T: Word> fmt::Display for Constraint<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let relation = match self {
+            Self::Zero(_) => "==",
+            Self::NonZero(_) => "!=",
+            Self::Negative(_) => "<",
+            Self::NonNegative(_) => ">=",
+        };
+        write!(f, "{} {} 0", self.expr(), relation)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolicError<T> {
+    Cpu(CpuError<T>),
+    SymbolicCode { pc: usize },
+    SymbolicAddress { pc: usize },
+    SymbolicJump { pc: usize },
+    Unsupported { pc: usize, mnemonic: &'static str },
+    Overflow { pc: usize },
+    StepLimit { pc: usize },
+    PathLimit,
+}
+
+impl<T: fmt::Display> fmt::Display for SymbolicError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cpu(error) => write!(f, "{}", error),
+            Self::SymbolicCode { pc } => write!(f, "Symbolic instruction at {}", pc),
+            Self::SymbolicAddress { pc } => write!(f, "Write to a symbolic address at {}", pc),
+            Self::SymbolicJump { pc } => write!(f, "Jump to a symbolic target at {}", pc),
+            Self::Unsupported { pc, mnemonic } => {
+                write!(f, "Can't execute {} symbolically at {}", mnemonic, pc)
+            }
+            Self::Overflow { pc } => write!(f, "Coefficient overflow at {}", pc),
+            Self::StepLimit { pc } => write!(f, "Step limit reached at {}", pc),
+            Self::PathLimit => write!(f, "Too many paths"),
+        }
+    }
+}
+
+impl<T: fmt::Debug + fmt::Display> Error for SymbolicError<T> {}
+
+impl<T> From<CpuError<T>> for SymbolicError<T> {
+    fn from(error: CpuError<T>) -> Self {
+        SymbolicError::Cpu(error)
+    }
+}
+
+// One way through the program, valid for every assignment satisfying its constraints
+#[derive(Debug, Clone)]
+pub struct Path<T> {
+    pub constraints: Vec<Constraint<T>>,
+    pub outputs: Vec<Expr<T>>,
+    memory: BTreeMap<usize, Expr<T>>,
+}
+
+impl<T: Word> Path<T> {
+    pub fn cell(&self, address: usize) -> Expr<T> {
+        self.memory.get(&address).cloned().unwrap_or_else(Expr::zero)
+    }
+
+    pub fn is_satisfied_by(&self, assignment: &[(&str, T)]) -> bool {
+        self.constraints.iter().all(|constraint| constraint.holds(assignment) == Some(true))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct State<T> {
+    pc: usize,
+    rbo: isize,
+    steps: usize,
+    reads: usize,
+    path: Path<T>,
+}
+
+enum Step<T> {
+    Next,
+    Fork(State<T>),
+    Halt,
+}
+
+impl<T: Word> State<T> {
+    fn word(&self) -> T {
+        self.path.cell(self.pc).as_constant().unwrap_or_else(T::zero)
+    }
+
+    // A parameter word may itself be symbolic, e.g. the noun and verb of day 2 are addresses.
+    // Loading through such an address gives an unknown value, named after the address and the
+    // step it was loaded at so that re-running a step after a fork names it the same way.
+    fn read(&self, offset: usize, param: &Parameter<T>) -> Result<Expr<T>, SymbolicError<T>> {
+        let raw = self.path.cell(self.pc + offset);
+        if let Parameter::Immediate(_) = param {
+            return Ok(raw);
+        }
+        if raw.as_constant().is_none() {
+            return Ok(Expr::variable(&format!("[{}]@{}", raw, self.steps)));
+        }
+        Ok(self.path.cell(self.address(offset, param)?))
+    }
+
+    fn address(&self, offset: usize, param: &Parameter<T>) -> Result<usize, SymbolicError<T>> {
+        if self.path.cell(self.pc + offset).as_constant().is_none() {
+            return Err(SymbolicError::SymbolicAddress { pc: self.pc });
+        }
+        match *param {
+            Parameter::Indexed(index) => Ok(index),
+            Parameter::Relative(offset) => {
+                let address =
+                    self.rbo.checked_add(offset).ok_or(SymbolicError::Overflow { pc: self.pc })?;
+                if address < 0 {
+                    let word = self.word();
+                    return Err(CpuError::NegativeAddress { pc: self.pc, word, address }.into());
+                }
+                Ok(address as usize)
+            }
+            Parameter::Immediate(_) => {
+                Err(CpuError::WriteToImmediate { pc: self.pc, word: self.word() }.into())
+            }
+        }
+    }
+
+    fn store(&mut self, address: usize, value: Expr<T>) {
+        self.path.memory.insert(address, value);
+    }
+
+    fn target(&self, offset: usize, param: &Parameter<T>) -> Result<usize, SymbolicError<T>> {
+        let target = self
+            .read(offset, param)?
+            .as_constant()
+            .ok_or(SymbolicError::SymbolicJump { pc: self.pc })?;
+        target.to_usize().ok_or_else(|| {
+            CpuError::JumpOutOfRange { pc: self.pc, word: self.word(), target }.into()
+        })
+    }
+
+    // Forks when the constraint isn't decided by the path so far. The other side restarts the
+    // current instruction with the negated constraint, which then decides it the other way.
+    fn decide(&mut self, constraint: Constraint<T>) -> (bool, Option<State<T>>) {
+        if let Some(value) = constraint.expr().as_constant() {
+            return (constraint.test(&value), None);
+        }
+        let negation = constraint.negation();
+        if self.path.constraints.contains(&constraint) {
+            return (true, None);
+        }
+        if self.path.constraints.contains(&negation) {
+            return (false, None);
+        }
+        let mut other = self.clone();
+        other.path.constraints.push(negation);
+        self.path.constraints.push(constraint);
+        (true, Some(other))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolicExecutor<T> {
+    pc: usize,
+    rbo: isize,
+    memory: BTreeMap<usize, Expr<T>>,
+    extensions: Extensions<T>,
+    inputs: Vec<Expr<T>>,
+    pub max_steps: usize,
+    pub max_paths: usize,
+}
+
+impl<T: Word> SymbolicExecutor<T> {
+    pub fn set_symbol(&mut self, address: usize, name: &str) {
+        self.memory.insert(address, Expr::variable(name));
+    }
+
+    // Reads past the provided inputs get fresh variables named input0, input1, ...
+    pub fn push_input(&mut self, input: Expr<T>) {
+        self.inputs.push(input);
+    }
+
+    pub fn run(&self) -> Result<Vec<Path<T>>, SymbolicError<T>> {
+        let path =
+            Path { constraints: Vec::new(), outputs: Vec::new(), memory: self.memory.clone() };
+        let mut pending = vec![State { pc: self.pc, rbo: self.rbo, steps: 0, reads: 0, path }];
+        let mut paths = Vec::new();
+
+        while let Some(mut state) = pending.pop() {
+            loop {
+                if state.steps == self.max_steps {
+                    return Err(SymbolicError::StepLimit { pc: state.pc });
+                }
+                let step = self.step(&mut state)?;
+                state.steps += 1;
+                match step {
+                    Step::Next => {}
+                    Step::Fork(other) => {
+                        if paths.len() + pending.len() + 2 > self.max_paths {
+                            return Err(SymbolicError::PathLimit);
+                        }
+                        pending.push(other);
+                    }
+                    Step::Halt => {
+                        paths.push(state.path);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(paths)
+    }
+
+    fn step(&self, state: &mut State<T>) -> Result<Step<T>, SymbolicError<T>> {
+        let pc = state.pc;
+        if state.path.cell(pc).as_constant().is_none() {
+            return Err(SymbolicError::SymbolicCode { pc });
+        }
+        // Symbolic parameter words decode as zeros and are dealt with by `State::read`
+        let instruction = Instruction::decode_with(
+            |index| state.path.cell(index).as_constant().unwrap_or_else(T::zero),
+            pc,
+            &self.extensions,
+        )?;
+        let overflow = || SymbolicError::Overflow { pc };
+
+        let mut fork = None;
+        let mut next = pc + instruction.len();
+        match &instruction {
+            Instruction::Add(a, b, c) | Instruction::Multiply(a, b, c) => {
+                let (a, b) = (state.read(1, a)?, state.read(2, b)?);
+                let result = match instruction {
+                    Instruction::Add(..) => a.checked_add(&b),
+                    _ => a.checked_mul(&b),
+                };
+                state.store(state.address(3, c)?, result.ok_or_else(overflow)?);
+            }
+            Instruction::Read(a) => {
+                let address = state.address(1, a)?;
+                let input = match self.inputs.get(state.reads) {
+                    Some(input) => input.clone(),
+                    None => Expr::variable(&format!("input{}", state.reads)),
+                };
+                state.reads += 1;
+                state.store(address, input);
+            }
+            Instruction::Write(a) => {
+                let output = state.read(1, a)?;
+                state.path.outputs.push(output);
+            }
+            Instruction::JumpIfTrue(a, b) | Instruction::JumpIfFalse(a, b) => {
+                let cond = state.read(1, a)?;
+                let constraint = match instruction {
+                    Instruction::JumpIfTrue(..) => Constraint::NonZero(cond),
+                    _ => Constraint::Zero(cond),
+                };
+                let (jumps, other) = state.decide(constraint);
+                fork = other;
+                if jumps {
+                    next = state.target(2, b)?;
+                }
+            }
+            Instruction::LessThan(a, b, c) | Instruction::Equals(a, b, c) => {
+                let diff =
+                    state.read(1, a)?.checked_sub(&state.read(2, b)?).ok_or_else(overflow)?;
+                let constraint = match instruction {
+                    Instruction::LessThan(..) => Constraint::Negative(diff),
+                    _ => Constraint::Zero(diff),
+                };
+                let (holds, other) = state.decide(constraint);
+                fork = other;
+                let value = if holds { T::one() } else { T::zero() };
+                state.store(state.address(3, c)?, Expr::constant(value));
+            }
+            Instruction::RelativeBaseOffset(a) => {
+                let offset = state.read(1, a)?.as_constant();
+                let offset = offset.and_then(|offset| offset.to_isize());
+                let offset = offset.ok_or(SymbolicError::SymbolicAddress { pc })?;
+                state.rbo = state.rbo.checked_add(offset).ok_or(SymbolicError::Overflow { pc })?;
+            }
+            Instruction::Break => return Ok(Step::Halt),
+            Instruction::Extended { mnemonic, .. } => {
+                return Err(SymbolicError::Unsupported { pc, mnemonic })
+            }
+        }
+
+        state.pc = next;
+        Ok(match fork {
+            Some(other) => Step::Fork(other),
+            None => Step::Next,
+        })
+    }
+}
+
+impl<T: Word, M: Memory<T>> CPU<T, M> {
+    // Starts from the current state of the CPU, with every cell concrete until marked symbolic
+    pub fn symbolic(&self) -> SymbolicExecutor<T> {
+        SymbolicExecutor {
+            pc: self.pc,
+            rbo: self.rbo,
+            memory: self
+                .mmu
+                .cells()
+                .filter(|(_, value)| !value.is_zero())
+                .map(|(address, value)| (address, Expr::constant(value)))
+                .collect(),
+            extensions: self.mmu.extensions.clone(),
+            inputs: Vec::new(),
+            max_steps: MAX_STEPS,
+            max_paths: MAX_PATHS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::asm::assemble;
+
+    const SQUARE: &str = "
+                RED IN(x)
+                JIT IN(x), IM(square)
+                WRT IM(0)
+                BRK
+        square: MUL IN(x), IN(x), IN(x)
+                WRT IN(x)
+                BRK
+        x:      DAT 0
+    ";
+
+    const LESS_THAN: &str = "
+                RED IN(x)
+                LST IN(x), IM(8), IN(flag)
+                WRT IN(flag)
+                MUL IN(x), IM(2), IN(x)
+                WRT IN(x)
+                BRK
+        x:      DAT 0
+        flag:   DAT 0
+    ";
+
+    #[test]
+    fn polynomials() {
+        let x = Expr::<i64>::variable("x");
+        let y = Expr::variable("y");
+        let sum = x.checked_add(&y).unwrap().checked_add(&Expr::constant(-2)).unwrap();
+        let square = sum.checked_mul(&sum).unwrap();
+
+        assert_eq!(square.to_string(), "x^2 + 2*x*y + y^2 - 4*x - 4*y + 4");
+        assert_eq!(square.degree(), 2);
+        assert_eq!(square.coefficient(&["y", "x"]), 2);
+        assert_eq!(square.evaluate(&[("x", 3), ("y", 4)]), Some(25));
+        assert_eq!(square.evaluate(&[("x", 3)]), None);
+        assert_eq!(square.checked_sub(&square).unwrap(), Expr::zero());
+        assert_eq!(x.checked_mul(&Expr::constant(0)).unwrap().as_constant(), Some(0));
+    }
+
+    #[test]
+    fn forks_on_branches() {
+        // Outputs the square of the input unless it's zero
+        let cpu = CPU::<i64>::new(assemble(SQUARE).unwrap());
+        let paths = cpu.symbolic().run().unwrap();
+        let summary: Vec<_> = paths
+            .iter()
+            .map(|path| (path.constraints[0].to_string(), path.outputs[0].to_string()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("input0 != 0".to_string(), "input0^2".to_string()),
+                ("input0 == 0".to_string(), "0".to_string())
+            ]
+        );
+
+        // Outputs whether the input is less than 8, then twice the input
+        let cpu = CPU::<i64>::new(assemble(LESS_THAN).unwrap());
+        let mut executor = cpu.symbolic();
+        executor.push_input(Expr::variable("x").checked_add(&Expr::constant(3)).unwrap());
+        let paths = executor.run().unwrap();
+        assert_eq!(paths.len(), 2);
+
+        let below: Vec<_> = paths.iter().filter(|path| path.is_satisfied_by(&[("x", 4)])).collect();
+        assert_eq!(below.len(), 1);
+        assert_eq!(below[0].constraints[0].to_string(), "x - 5 < 0");
+        assert_eq!(below[0].outputs[0], Expr::constant(1));
+        assert_eq!(below[0].outputs[1].to_string(), "2*x + 6");
+
+        // A relative base past the range of addresses is an error rather than a panic
+        let cpu = CPU::<i64>::from_source("109,9223372036854775807,109,1,99");
+        assert_eq!(cpu.symbolic().run().unwrap_err(), SymbolicError::Overflow { pc: 2 });
+    }
+
+    #[test]
+    fn solves_day02_linearly() {
+        let cpu = CPU::<i64>::from_source(include_str!("../../day02/input"));
+        let mut executor = cpu.symbolic();
+        executor.set_symbol(1, "noun");
+        executor.set_symbol(2, "verb");
+
+        let paths = executor.run().unwrap();
+        assert_eq!(paths.len(), 1);
+        let result = paths[0].cell(0);
+        assert_eq!(result.degree(), 1);
+        assert_eq!(result.evaluate(&[("noun", 12), ("verb", 2)]), Some(5_098_658));
+
+        // Adding to the opcode of the next instruction makes it symbolic
+        let cpu = CPU::<i64>::from_source("1,1,1,4,99,5,6,0,99");
+        let mut executor = cpu.symbolic();
+        executor.set_symbol(1, "a");
+        assert!(matches!(executor.run(), Err(SymbolicError::SymbolicCode { pc: 4 })));
+    }
+}
@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::fmt;
+
+use super::memory::{DenseMemory, Memory};
+use super::resume::Status;
+use super::{CpuError, Word, CPU};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineError<T> {
+    pub machine: usize,
+    pub error: CpuError<T>,
+}
+
+impl<T: fmt::Display> fmt::Display for MachineError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on machine {}", self.error, self.machine)
+    }
+}
+
+impl<T: fmt::Debug + fmt::Display> Error for MachineError<T> {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report<T> {
+    pub outputs: Vec<Vec<T>>,
+    // Machines left waiting for input that will never arrive
+    pub blocked: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Machine<T, M> {
+    cpu: CPU<T, M>,
+    outputs: Vec<T>,
+    targets: Vec<usize>,
+    halted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Topology<T, M = DenseMemory<T>> {
+    machines: Vec<Machine<T, M>>,
+}
+
+impl<T: Word, M: Memory<T> + Clone> Topology<T, M> {
+    // Each machine gets its own copy of the program and its phase as the first input
+    pub fn pipeline(cpu: &CPU<T, M>, phases: &[T]) -> Topology<T, M> {
+        let mut topology = Topology::new();
+        for phase in phases {
+            let machine = topology.add_machine(cpu.clone(), vec![phase.clone()]);
+            if machine > 0 {
+                topology.connect(machine - 1, machine);
+            }
+        }
+        topology
+    }
+
+    pub fn ring(cpu: &CPU<T, M>, phases: &[T]) -> Topology<T, M> {
+        let mut topology = Topology::pipeline(cpu, phases);
+        if !phases.is_empty() {
+            topology.connect(phases.len() - 1, 0);
+        }
+        topology
+    }
+}
+
+impl<T: Word, M: Memory<T>> Default for Topology<T, M> {
+    fn default() -> Topology<T, M> {
+        Topology::new()
+    }
+}
+
+impl<T: Word, M: Memory<T>> Topology<T, M> {
+    pub fn new() -> Topology<T, M> {
+        Topology { machines: Vec::new() }
+    }
+
+    pub fn add_machine(&mut self, mut cpu: CPU<T, M>, inputs: Vec<T>) -> usize {
+        cpu.provide_inputs(inputs);
+        self.machines.push(Machine {
+            cpu,
+            outputs: Vec::new(),
+            targets: Vec::new(),
+            halted: false,
+        });
+        self.machines.len() - 1
+    }
+
+    // Connecting one machine to several others copies every output to all of them
+    pub fn connect(&mut self, from: usize, to: usize) {
+        assert!(to < self.machines.len(), "No machine {}", to);
+        self.machines[from].targets.push(to);
+    }
+
+    pub fn send(&mut self, machine: usize, value: T) {
+        self.machines[machine].cpu.provide_input(value);
+    }
+
+    pub fn outputs(&self, machine: usize) -> &[T] {
+        &self.machines[machine].outputs
+    }
+
+    // Machines run in turns until each one has either halted or is waiting on an empty input
+    pub fn run(&mut self) -> Result<Report<T>, MachineError<T>> {
+        loop {
+            for machine in 0..self.machines.len() {
+                let outputs = self.turn(machine)?;
+                for target in self.machines[machine].targets.clone() {
+                    self.machines[target].cpu.provide_inputs(outputs.iter().cloned());
+                }
+            }
+
+            if self
+                .machines
+                .iter()
+                .all(|machine| machine.halted || machine.cpu.pending_inputs() == 0)
+            {
+                break;
+            }
+        }
+
+        Ok(Report {
+            outputs: self.machines.iter().map(|machine| machine.outputs.clone()).collect(),
+            blocked: (0..self.machines.len()).filter(|&i| !self.machines[i].halted).collect(),
+        })
+    }
+
+    fn turn(&mut self, index: usize) -> Result<Vec<T>, MachineError<T>> {
+        let machine = &mut self.machines[index];
+        if machine.halted {
+            return Ok(Vec::new());
+        }
+        let status = machine
+            .cpu
+            .run_until_blocked()
+            .map_err(|error| MachineError { machine: index, error })?;
+        machine.halted = status == Status::Halted;
+        let outputs = machine.cpu.take_outputs();
+        machine.outputs.extend(outputs.iter().cloned());
+        Ok(outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Adds its two inputs and outputs the sum
+    const ADDER: &str = "3,11,3,12,1,11,12,11,4,11,99,0,0";
+
+    #[test]
+    fn fan_out_and_join() {
+        let adder = CPU::<i64>::from_source(ADDER);
+        let mut topology = Topology::new();
+        let source = topology.add_machine(adder.clone(), vec![1, 2]);
+        let left = topology.add_machine(adder.clone(), vec![10]);
+        let right = topology.add_machine(adder.clone(), vec![100]);
+        let sink = topology.add_machine(adder, vec![]);
+        topology.connect(source, left);
+        topology.connect(source, right);
+        topology.connect(left, sink);
+        topology.connect(right, sink);
+
+        let report = topology.run().unwrap();
+        assert_eq!(report.outputs, vec![vec![3], vec![13], vec![103], vec![116]]);
+        assert!(report.blocked.is_empty());
+    }
+
+    #[test]
+    fn starved_machines_are_reported() {
+        let mut topology = Topology::pipeline(&CPU::<i64>::from_source(ADDER), &[1, 2]);
+        let report = topology.run().unwrap();
+        assert_eq!(report.outputs, vec![Vec::<i64>::new(), vec![]]);
+        assert_eq!(report.blocked, vec![0, 1]);
+
+        topology.send(0, 5);
+        assert_eq!(topology.run().unwrap().outputs, vec![vec![6], vec![8]]);
+        assert_eq!(topology.outputs(1), &[8]);
+    }
+}
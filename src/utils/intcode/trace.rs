@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use super::{ExecutionResult, Instruction, Word, CPU};
+
+#[derive(Debug, Clone)]
+pub struct TraceEntry<T> {
+    pub pc: usize,
+    pub instruction: Instruction<T>,
+    pub reads: Vec<T>,
+    pub write: Option<(usize, T)>,
+    pub rbo: Option<isize>,
+    pub input: Option<T>,
+    pub output: Option<T>,
+}
+
+impl<T> TraceEntry<T> {
+    pub(super) fn new(pc: usize, instruction: Instruction<T>) -> TraceEntry<T> {
+        TraceEntry {
+            pc,
+            instruction,
+            reads: Vec::new(),
+            write: None,
+            rbo: None,
+            input: None,
+            output: None,
+        }
+    }
+}
+
+impl<T: Word> fmt::Display for TraceEntry<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>5}: {} ;", self.pc, self.instruction)?;
+        if !self.reads.is_empty() {
+            let reads: Vec<_> = self.reads.iter().map(T::to_string).collect();
+            write!(f, " read={}", reads.join(","))?;
+        }
+        if let Some((address, value)) = &self.write {
+            write!(f, " mem[{}]={}", address, value)?;
+        }
+        if let Some(rbo) = self.rbo {
+            write!(f, " rbo={}", rbo)?;
+        }
+        if let Some(input) = &self.input {
+            write!(f, " in={}", input)?;
+        }
+        if let Some(output) = &self.output {
+            write!(f, " out={}", output)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn write_trace<T: Word>(trace: &[TraceEntry<T>], mut out: impl Write) -> io::Result<()> {
+    for entry in trace {
+        writeln!(out, "{}", entry)?;
+    }
+    Ok(())
+}
+
+impl<T, M> CPU<T, M> {
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    pub fn stop_trace(&mut self) -> Vec<TraceEntry<T>> {
+        self.trace.take().unwrap_or_default()
+    }
+
+    pub fn trace(&self) -> Option<&[TraceEntry<T>]> {
+        self.trace.as_deref()
+    }
+
+    pub(super) fn trace_entry(&mut self) -> Option<&mut TraceEntry<T>> {
+        self.trace.as_mut().and_then(|trace| trace.last_mut())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: usize,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |line: &Option<String>| line.clone().unwrap_or_else(|| "<end>".to_string());
+        write!(
+            f,
+            "Diverged at step {}\nexpected: {}\n  actual: {}",
+            self.step,
+            describe(&self.expected),
+            describe(&self.actual)
+        )
+    }
+}
+
+fn recorded_input<T: FromStr>(line: &str) -> Option<T> {
+    let (_, effects) = line.split_at(line.find(';')?);
+    effects.split_whitespace().find_map(|token| token.strip_prefix("in=")?.parse().ok())
+}
+
+pub fn replay<T>(source: &str, trace: &str) -> Result<usize, Divergence>
+where
+    T: Word + FromStr,
+{
+    let expected: Vec<_> = trace.lines().map(str::trim_end).filter(|l| !l.is_empty()).collect();
+    let mut inputs: VecDeque<T> = expected.iter().filter_map(|line| recorded_input(line)).collect();
+
+    let mut cpu = CPU::<T>::from_source(source);
+    cpu.start_trace();
+
+    let mut error = None;
+    while cpu.trace().map_or(0, <[_]>::len) <= expected.len() {
+        let instruction = match cpu.try_fetch_instruction() {
+            Ok(instruction) => instruction,
+            Err(err) => {
+                error = Some(err);
+                break;
+            }
+        };
+        match cpu.try_execute_instruction(instruction) {
+            Ok(ExecutionResult::YieldedInput(sink)) => match inputs.pop_front() {
+                Some(value) => sink(value),
+                None => break,
+            },
+            Ok(ExecutionResult::Completed) => break,
+            Ok(_) => {}
+            Err(err) => {
+                error = Some(err);
+                break;
+            }
+        }
+    }
+
+    // Traces end where the recorded run failed, so an error only diverges if it comes early
+    let mut actual: Vec<_> = cpu.stop_trace().iter().map(ToString::to_string).collect();
+    if let Some(err) = error.filter(|_| actual.len() < expected.len()) {
+        actual.push(format!("error: {}", err));
+    }
+
+    for step in 0..expected.len().max(actual.len()) {
+        let expected = expected.get(step).map(|line| line.to_string());
+        let actual = actual.get(step).cloned();
+        if expected != actual {
+            return Err(Divergence { step, expected, actual });
+        }
+    }
+
+    Ok(expected.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::resume::Status;
+
+    const INPUT: &str = include_str!("../../day05/input");
+
+    fn record(source: &str, input: i32) -> String {
+        let mut cpu = CPU::<i32>::from_source(source);
+        cpu.start_trace();
+        cpu.outputs_with(|| input).last();
+
+        let mut out = Vec::new();
+        write_trace(&cpu.stop_trace(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn records_effects() {
+        let trace = record("3,11,1001,11,5,11,109,9,204,2,99", 4);
+        assert_eq!(
+            trace.lines().collect::<Vec<_>>(),
+            vec![
+                "    0: RED IN(11) ; mem[11]=4 in=4",
+                "    2: ADD IN(11), IM(5), IN(11) ; read=4,5 mem[11]=9",
+                "    6: RBO IM(9) ; read=9 rbo=9",
+                "    8: WRT RE(2) ; read=9 out=9",
+                "   10: BRK ;",
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_runs_replay() {
+        // The first fails to decode 42, the second fails to write below address 0 after reading
+        for source in &["104,1,42", "109,-5,21101,1,1,0,99"] {
+            let mut cpu = CPU::<i32>::from_source(source);
+            cpu.start_trace();
+            while let Ok(Status::Output(_)) = cpu.try_run() {}
+
+            let trace = cpu.stop_trace();
+            assert_eq!(trace.len(), 1);
+            let mut out = Vec::new();
+            write_trace(&trace, &mut out).unwrap();
+            assert_eq!(replay::<i32>(source, &String::from_utf8(out).unwrap()), Ok(1));
+        }
+
+        let divergence =
+            replay::<i32>("104,1,42", "    0: WRT IM(1) ; read=1 out=1\n    2: BRK ;").unwrap_err();
+        assert_eq!(divergence.step, 1);
+        assert_eq!(divergence.actual.as_deref(), Some("error: Invalid opcode in 42 at 2"));
+    }
+
+    #[test]
+    fn replays_traces() {
+        let trace = record(INPUT, 5);
+        assert_eq!(replay::<i32>(INPUT, &trace), Ok(trace.lines().count()));
+
+        // Comparing a different address changes the outcome of the first equality test
+        let changed = INPUT.replacen("1008,226,677,224", "1008,226,226,224", 1);
+        let divergence = replay::<i32>(&changed, &trace).unwrap_err();
+        let expected = divergence.expected.unwrap();
+        assert!(expected.contains("EQL"), "{}", expected);
+        assert_ne!(Some(expected), divergence.actual);
+
+        let truncated: String = trace.lines().take(10).map(|line| format!("{}\n", line)).collect();
+        assert_eq!(replay::<i32>(INPUT, &truncated).unwrap_err().step, 10);
+    }
+}
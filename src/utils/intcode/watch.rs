@@ -0,0 +1,159 @@
+use std::fmt;
+use std::ops::Range;
+
+use super::memory::Memory;
+use super::{CpuError, Instruction, Word, CPU, MMU};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, Access::Read)
+                | (WatchKind::Write, Access::Write)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addresses: Range<usize>,
+    pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchEvent<T> {
+    pub pc: usize,
+    // Code can overwrite itself with something that no longer decodes
+    pub instruction: Result<Instruction<T>, CpuError<T>>,
+    pub access: Access,
+    pub address: usize,
+    pub old: T,
+    pub new: T,
+}
+
+impl<T: Word> fmt::Display for WatchEvent<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = match &self.instruction {
+            Ok(instruction) => instruction.to_string(),
+            Err(err) => format!("DAT {}", err.word()),
+        };
+        match self.access {
+            Access::Read => {
+                write!(f, "{:>5}: {} read {} from {}", self.pc, code, self.old, self.address)
+            }
+            Access::Write => write!(
+                f,
+                "{:>5}: {} wrote {} to {} (was {})",
+                self.pc, code, self.new, self.address, self.old
+            ),
+        }
+    }
+}
+
+impl<T: Word, M: Memory<T>> MMU<T, M> {
+    pub(super) fn record(&mut self, access: Access, address: usize, pc: usize, old: &T, new: &T) {
+        let watched = self
+            .watchpoints
+            .iter()
+            .any(|watch| watch.kind.matches(access) && watch.addresses.contains(&address));
+
+        if watched {
+            let instruction = Instruction::decode(self, pc);
+            let (old, new) = (old.clone(), new.clone());
+            self.events.push(WatchEvent { pc, instruction, access, address, old, new });
+        }
+    }
+}
+
+impl<T, M> CPU<T, M> {
+    pub fn watch(&mut self, addresses: Range<usize>, kind: WatchKind) {
+        self.mmu.watchpoints.push(Watchpoint { addresses, kind });
+    }
+
+    pub fn unwatch(&mut self, addresses: Range<usize>) {
+        self.mmu.watchpoints.retain(|watch| watch.addresses != addresses);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.mmu.watchpoints
+    }
+
+    pub fn watch_events(&self) -> &[WatchEvent<T>] {
+        &self.mmu.events
+    }
+
+    pub fn take_watch_events(&mut self) -> Vec<WatchEvent<T>> {
+        std::mem::take(&mut self.mmu.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_watched_accesses() {
+        // Counts down from the input, outputting every value
+        let mut cpu = CPU::<i64>::from_source("3,12,4,12,1001,12,-1,12,1005,12,2,99,0");
+        cpu.watch(12..13, WatchKind::Write);
+        cpu.watch(10..12, WatchKind::Read);
+
+        assert_eq!(cpu.outputs_with(|| 2).collect::<Vec<_>>(), vec![2, 1]);
+
+        let events: Vec<_> = cpu.watch_events().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            events,
+            vec![
+                "    0: RED IN(12) wrote 2 to 12 (was 0)",
+                "    4: ADD IN(12), IM(-1), IN(12) wrote 1 to 12 (was 2)",
+                "    4: ADD IN(12), IM(-1), IN(12) wrote 0 to 12 (was 1)",
+            ]
+        );
+
+        cpu.watch(0..100, WatchKind::Read);
+        assert_eq!(cpu.take_watch_events().len(), 3);
+        assert!(cpu.watch_events().is_empty());
+    }
+
+    #[test]
+    fn records_reads_through_relative_parameters() {
+        let mut cpu = CPU::<i64>::from_source("109,5,204,2,99,0,0,42");
+        cpu.watch(0..8, WatchKind::ReadWrite);
+        assert_eq!(cpu.outputs().collect::<Vec<_>>(), vec![42]);
+
+        let events = cpu.watch_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].pc, events[0].access, events[0].address), (2, Access::Read, 7));
+        assert_eq!((events[0].old, events[0].new), (42, 42));
+
+        cpu.unwatch(0..8);
+        assert!(cpu.watchpoints().is_empty());
+    }
+
+    #[test]
+    fn records_writes_that_break_the_instruction() {
+        // Overwrites its own opcode with 77, which doesn't decode any more
+        let mut cpu = CPU::<i64>::from_source("1101,7,70,0,99");
+        cpu.watch(0..1, WatchKind::Write);
+        assert_eq!(cpu.outputs().count(), 0);
+
+        let events = cpu.watch_events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0].instruction, Err(CpuError::InvalidOpcode { pc: 0, word: 77 })));
+        assert_eq!(events[0].to_string(), "    0: DAT 77 wrote 77 to 0 (was 1101)");
+    }
+}
@@ -0,0 +1,129 @@
+use num::{BigInt, CheckedAdd, CheckedMul, FromPrimitive, Signed, ToPrimitive};
+use std::fmt;
+use std::hash::Hash;
+
+pub trait Word:
+    Clone
+    + Ord
+    + Hash
+    + Signed
+    + CheckedAdd
+    + CheckedMul
+    + ToPrimitive
+    + FromPrimitive
+    + fmt::Debug
+    + fmt::Display
+{
+    fn wrapping_add(&self, other: &Self) -> Self;
+
+    fn wrapping_mul(&self, other: &Self) -> Self;
+
+    fn saturating_add(&self, other: &Self) -> Self;
+
+    fn saturating_mul(&self, other: &Self) -> Self;
+}
+
+macro_rules! primitive_word {
+    ($($t:ty),*) => {$(
+        impl Word for $t {
+            fn wrapping_add(&self, other: &Self) -> Self {
+                <$t>::wrapping_add(*self, *other)
+            }
+
+            fn wrapping_mul(&self, other: &Self) -> Self {
+                <$t>::wrapping_mul(*self, *other)
+            }
+
+            fn saturating_add(&self, other: &Self) -> Self {
+                <$t>::saturating_add(*self, *other)
+            }
+
+            fn saturating_mul(&self, other: &Self) -> Self {
+                <$t>::saturating_mul(*self, *other)
+            }
+        }
+    )*};
+}
+
+primitive_word!(i8, i16, i32, i64, i128, isize);
+
+// Big integers never overflow, so every policy behaves the same
+impl Word for BigInt {
+    fn wrapping_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn wrapping_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn saturating_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn saturating_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Arithmetic {
+    Wrapping,
+    #[default]
+    Checked,
+    Saturating,
+}
+
+impl Arithmetic {
+    pub fn add<T: Word>(self, a: &T, b: &T) -> Option<T> {
+        match self {
+            Self::Wrapping => Some(a.wrapping_add(b)),
+            Self::Checked => a.checked_add(b),
+            Self::Saturating => Some(a.saturating_add(b)),
+        }
+    }
+
+    pub fn mul<T: Word>(self, a: &T, b: &T) -> Option<T> {
+        match self {
+            Self::Wrapping => Some(a.wrapping_mul(b)),
+            Self::Checked => a.checked_mul(b),
+            Self::Saturating => Some(a.saturating_mul(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::intcode::{CpuError, CPU};
+
+    // Squares 1000 three times and outputs the result
+    const SQUARES: &str = "2,15,15,15,2,15,15,15,2,15,15,15,4,15,99,1000";
+
+    fn run(arithmetic: Arithmetic) -> Vec<Result<i64, CpuError<i64>>> {
+        let mut cpu = CPU::from_source(SQUARES);
+        cpu.set_arithmetic(arithmetic);
+        cpu.try_outputs_with(|| 0).collect()
+    }
+
+    #[test]
+    fn arithmetic_policies() {
+        assert_eq!(run(Arithmetic::Checked), vec![Err(CpuError::Overflow { pc: 8, word: 2 })]);
+        assert_eq!(run(Arithmetic::Saturating), vec![Ok(i64::MAX)]);
+        assert_eq!(
+            run(Arithmetic::Wrapping),
+            vec![Ok(1_000_000_000_000i64.wrapping_mul(1_000_000_000_000))]
+        );
+    }
+
+    #[test]
+    fn big_integers() {
+        let outputs: Vec<BigInt> = CPU::from_source(SQUARES).outputs().collect();
+        assert_eq!(outputs, vec!["1000000000000000000000000".parse().unwrap()]);
+
+        let boost = include_str!("../../day09/input");
+        let outputs: Vec<BigInt> =
+            CPU::from_source(boost).outputs_with(|| BigInt::from(1)).collect();
+        assert_eq!(outputs, vec![BigInt::from(2_752_191_671i64)]);
+    }
+}